@@ -7,16 +7,109 @@ fn main() {
     let out_dir = env::var_os("OUT_DIR").unwrap();
     let dest_path = Path::new(&out_dir).join("asset_list.rs");
     let mut asset_list_file = File::create(dest_path).unwrap();
+    println!("cargo:rerun-if-changed=models");
 
     let items = fs::read_dir(Path::new("models/")).unwrap();
+    let mut errors = Vec::new();
     write!(asset_list_file, "const ASSET_LIST: &[&str] = &[\n").unwrap();
     for item in items {
         let item = item.unwrap();
         let path = item.path();
         if path.is_file() {
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("gltf") => errors.extend(validate_gltf(&path)),
+                Some("obj") => errors.extend(validate_obj(&path)),
+                _ => (),
+            }
             let path = path.file_name().unwrap().to_str().unwrap();
             write!(asset_list_file, "\"{}\",\n", path).unwrap();
         }
     }
     write!(asset_list_file, "];").unwrap();
+    if !errors.is_empty() {
+        panic!("Invalid assets in models/:\n  {}", errors.join("\n  "));
+    }
+}
+
+/// Catches the mistakes that otherwise only show up as a blank canvas at runtime.
+fn validate_gltf(path: &Path) -> Vec<String> {
+    let name = path.display();
+    let mut errors = Vec::new();
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return vec![format!("{}: could not be read: {}", name, e)],
+    };
+    let json: serde_json::Value = match serde_json::from_str(&text) {
+        Ok(json) => json,
+        Err(e) => return vec![format!("{}: is not valid JSON: {}", name, e)],
+    };
+    match json["asset"]["version"].as_str() {
+        Some(version) if version.starts_with("2.") => (),
+        Some(version) => errors.push(format!("{}: asset.version is \"{}\", only glTF 2.x is supported", name, version)),
+        None => errors.push(format!("{}: missing asset.version, is this really a glTF file?", name)),
+    }
+    if json["meshes"].as_array().map_or(true, |m| m.is_empty()) {
+        errors.push(format!("{}: has no meshes, nothing would be drawable", name));
+    }
+    if let Some(meshes) = json["meshes"].as_array() {
+        for (index, mesh) in meshes.iter().enumerate() {
+            if mesh["name"].as_str().is_none() {
+                // Legal glTF, the loader skips it, but it can't be spawned
+                println!("cargo:warning={}: mesh {} has no name and will be skipped, renderers are looked up by mesh name", name, index);
+            }
+        }
+    }
+    let dir = path.parent().unwrap();
+    for section in &["buffers", "images"] {
+        let entries = match json[*section].as_array() {
+            Some(entries) => entries,
+            None => continue,
+        };
+        for (index, entry) in entries.iter().enumerate() {
+            let uri = match entry["uri"].as_str() {
+                Some(uri) => uri,
+                None => continue,
+            };
+            if uri.starts_with("data:") {
+                continue;
+            }
+            if !dir.join(uri).is_file() {
+                errors.push(format!("{}: {}[{}] references \"{}\" which isn't in models/", name, section, index, uri));
+            }
+        }
+    }
+    errors
+}
+
+/// Faces pointing at vertices that don't exist, or no faces at all.
+fn validate_obj(path: &Path) -> Vec<String> {
+    let name = path.display();
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return vec![format!("{}: could not be read: {}", name, e)],
+    };
+    let mut errors = Vec::new();
+    let (mut positions, mut faces) = (0i64, 0);
+    for (number, line) in text.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => positions += 1,
+            Some("f") => {
+                faces += 1;
+                for corner in tokens {
+                    let index = corner.split('/').next().and_then(|v| v.parse::<i64>().ok());
+                    match index {
+                        Some(index) if index != 0 && index.abs() <= positions => (),
+                        _ => errors.push(format!("{}:{}: face corner \"{}\" isn't one of the {} vertices so far", name, number + 1, corner, positions)),
+                    }
+                }
+            },
+            Some("mtllib") => println!("cargo:warning={}: materials aren't loaded for .obj, it will draw plain white", name),
+            _ => (),
+        }
+    }
+    if faces == 0 {
+        errors.push(format!("{}: has no faces, nothing would be drawable", name));
+    }
+    errors
 }
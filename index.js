@@ -1,6 +1,5 @@
 const rust = import('./pkg/crabmancake.js');
 const canvas = document.getElementById('rustCanvas');
-const gl = canvas.getContext("webgl", { antialias: true});
 
 async function crab() {
     let mod = await rust;
@@ -8,7 +7,9 @@ async function crab() {
     mod.cmc_init();
 
     const FPS_THROTTLE = 1000.0 / 30.0;
-    const cmcClient = await new mod.CmcClient();
+    const contextOptions = new mod.ContextOptions();
+    contextOptions.antialias = true;
+    const cmcClient = await new mod.CmcClient(contextOptions);
     const initialTime = Date.now();
     let lastDrawTime = -1;
 
@@ -26,8 +27,6 @@ async function crab() {
                 canvas.width = window.innerWidth;
                 canvas.clientWidth = window.innerWidth;
                 canvas.style.width = window.innerWidth;
-
-                gl.viewport(0, 0, window.innerWidth, window.innerHeight);
             }
             let elapsedTime = currTime - initialTime;
             cmcClient.update(elapsedTime, window.innerHeight, window.innerWidth);
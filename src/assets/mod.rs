@@ -1,6 +1,8 @@
 use crate::error::CmcResult;
-use futures::{StreamExt, stream::FuturesUnordered};
-use model::{build_fetcher, load_images, load_buffers};
+use futures::{FutureExt, StreamExt, stream::FuturesUnordered};
+use model::{build_fetcher, deferred_images, load_buffers, load_deferred_image, load_image, texture_transforms};
+use image::DynamicImage;
+use std::sync::{Arc, RwLock};
 use std::path::Path;
 use asset_list::get_asset_list;
 use web_sys::Window;
@@ -8,36 +10,128 @@ use gltf::Gltf;
 
 mod asset_list;
 mod model;
+mod obj;
+pub mod progress;
 
-pub use model::Model;
+pub use model::{DeferredImage, Model, ModelCamera};
+pub use obj::{ObjModel, ObjObject};
 
 pub const MODEL_DIR: &str = "models";
 
-pub async fn load_models(server_root: String, window: &Window) -> CmcResult<Vec<Model>> {
+/// Something that won't show up, kept so the page can tell the user rather than wait on it.
+#[derive(Clone, Debug)]
+pub struct FailedAsset {
+    pub name: String,
+    pub error: String,
+}
+
+impl FailedAsset {
+    fn new<E: std::fmt::Display>(name: &str, error: E) -> Self {
+        report_error!("Failed to load {}: {}", name, error);
+        Self { name: name.to_string(), error: error.to_string() }
+    }
+}
+
+/// Models that loaded, by format, and the ones that didn't with why.
+pub struct LoadedModels {
+    pub gltf: Vec<Model>,
+    pub obj: Vec<ObjModel>,
+    pub failed: Vec<FailedAsset>,
+}
+
+pub async fn load_models(server_root: String, window: &Window) -> CmcResult<LoadedModels> {
     log::info!("Server root: {}", server_root);
     let fetchers = FuturesUnordered::new();
     let mut models = Vec::new();
+    let mut obj_models = Vec::new();
+    let mut failed = Vec::new();
     for item in get_asset_list() {
         let path = Path::new(item);
         let uri = format!("{}/{}/{}",server_root, MODEL_DIR, item);
-        let extension = path.extension().unwrap().to_str();
-        if let Some("gltf") = extension {
-            fetchers.push(build_fetcher(uri.clone(), window));
+        let extension = path.extension().and_then(|e| e.to_str());
+        // A .glb carries its own buffers, load_buffers finds them in the blob
+        if let Some("gltf") | Some("glb") | Some("obj") = extension {
+            let name = path.file_stem().map_or(item.to_string(), |stem| stem.to_string_lossy().to_string());
+            let is_obj = extension == Some("obj");
+            fetchers.push(build_fetcher(uri.clone(), window).map(move |fetched| (name, is_obj, fetched)));
         }
     }
-    let fetch_results = fetchers.collect::<Vec<CmcResult<Vec<u8>>>>().await;
-    for fetched in fetch_results {
+    let fetch_results = fetchers.collect::<Vec<(String, bool, CmcResult<Vec<u8>>)>>().await;
+    for (name, is_obj, fetched) in fetch_results {
         match fetched {
+            Ok(buffer) if is_obj => {
+                match obj::parse_obj(&name, &String::from_utf8_lossy(&buffer)) {
+                    Ok(model) => {
+                        pipeline_trace!(Parsed, name, "{} objects", model.objects.len());
+                        obj_models.push(model);
+                    },
+                    Err(e) => failed.push(FailedAsset::new(&name, e)),
+                }
+            },
             Ok(buffer) => {
-                let gltf = Gltf::from_slice(&buffer[..])?;
-                let images = load_images(&gltf, server_root.as_str(), window).await?;
-                let buffers = load_buffers(&gltf, server_root.as_str(), window).await?;
-                models.push(Model {gltf, buffers, images});
+                let gltf = match Gltf::from_slice(&buffer[..]) {
+                    Ok(gltf) => gltf,
+                    Err(e) => {
+                        failed.push(FailedAsset::new(&name, e));
+                        continue;
+                    },
+                };
+                pipeline_trace!(Parsed, name, "{} meshes, {} images", gltf.meshes().len(), gltf.images().len());
+                let texture_transforms = texture_transforms(&buffer[..]);
+                let buffers = match load_buffers(&gltf, server_root.as_str(), window).await {
+                    Ok(buffers) => buffers,
+                    Err(e) => {
+                        failed.push(FailedAsset::new(&name, e));
+                        continue;
+                    },
+                };
+                pipeline_trace!(BuffersResolved, name, "{} of {} buffers", buffers.len(), gltf.buffers().len());
+                let deferred_images = deferred_images(&gltf, &buffers, server_root.as_str());
+                models.push(Model {name, gltf, buffers, deferred_images, texture_transforms});
             },
-            Err(e) => {
-                log::error!("Failed to fetch model: {}", e);
+            Err(e) => failed.push(FailedAsset::new(&name, e)),
+        }
+    }
+    Ok(LoadedModels { gltf: models, obj: obj_models, failed })
+}
+
+/// Fetches a single image by uri into slot, used for the environment.
+pub async fn fetch_image_into(uri: String, slot: Arc<RwLock<Option<DynamicImage>>>, failed: Arc<RwLock<Vec<FailedAsset>>>) {
+    let window = match web_sys::window() {
+        Some(window) => window,
+        None => return report_error!("No window to fetch {} with", uri),
+    };
+    match load_image(&uri, None, &window).await {
+        Ok(decoded) => *slot.write().unwrap() = Some(decoded),
+        Err(e) => failed.write().unwrap().push(FailedAsset::new(&uri, e)),
+    }
+}
+
+pub struct ArrivedImage {
+    pub model: String,
+    pub index: usize,
+    pub image: DynamicImage,
+}
+
+/// Fetches every deferred image in turn, handing each one over as soon as it decodes.
+/// finished is set once every image has either arrived or failed.
+pub async fn stream_images(deferred: Vec<(String, DeferredImage)>, arrived: Arc<RwLock<Vec<ArrivedImage>>>, finished: Arc<RwLock<bool>>, failed: Arc<RwLock<Vec<FailedAsset>>>) {
+    let window = match web_sys::window() {
+        Some(window) => window,
+        None => {
+            report_error!("No window to stream images with");
+            *finished.write().unwrap() = true;
+            return;
+        },
+    };
+    for (model, image) in deferred {
+        match load_deferred_image(&image, &window).await {
+            Ok(decoded) => {
+                arrived.write().unwrap().push(ArrivedImage { model, index: image.index, image: decoded });
             },
+            // The texture keeps its placeholder
+            Err(e) => failed.write().unwrap().push(FailedAsset::new(&format!("{} {}", model, image.describe()), e)),
         }
     }
-    Ok(models)
+    *finished.write().unwrap() = true;
 }
@@ -1,89 +1,319 @@
-use super::MODEL_DIR;
-use futures::{TryFutureExt, TryStreamExt};
+use super::{MODEL_DIR, progress};
+use futures::StreamExt;
 use crate::error::{CmcError, CmcResult};
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
 use wasm_streams::ReadableStream;
 use web_sys::{Request, RequestInit, RequestMode, Response, Window};
-use js_sys::Uint8Array;
-use gltf::{buffer::Source as BufSource, Gltf, image::Source as ImgSource};
-use image::DynamicImage;
+use js_sys::{Promise, Uint8Array};
+use gltf::{buffer::Source as BufSource, camera::Projection, Glb, Gltf, image::Source as ImgSource};
+use nalgebra::{Matrix3, Quaternion, UnitQuaternion, Vector3};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use image::{DynamicImage, ImageFormat};
+
+/// Tries per file before it counts as failed, transient network errors and 5xx are retried.
+const FETCH_ATTEMPTS: u32 = 4;
+/// Wait before the first retry, doubling for each one after.
+const FETCH_BACKOFF_MS: i32 = 250;
 
 pub struct Model {
+    pub name: String,
     pub gltf: Gltf,
     pub buffers: Vec<Vec<u8>>,
-    pub images: Vec<DynamicImage>,
+    pub deferred_images: Vec<DeferredImage>,
+    /// Base color KHR_texture_transform per material index, missing means identity.
+    pub texture_transforms: HashMap<usize, Matrix3<f32>>,
+}
+
+/// An image that is fetched after the model's geometry is already on screen.
+#[derive(Clone, Debug)]
+pub struct DeferredImage {
+    pub index: usize,
+    pub source: DeferredSource,
+    /// What the glTF says the image is, glTF lets uri images leave it out.
+    pub mime_type: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum DeferredSource {
+    Uri(String),
+    /// Already in one of the model's buffers, as images in a .glb are, so only decoding is left.
+    Embedded(Vec<u8>),
+}
+
+impl DeferredImage {
+    pub fn describe(&self) -> String {
+        match &self.source {
+            DeferredSource::Uri(uri) => uri.clone(),
+            DeferredSource::Embedded(bytes) => format!("embedded image {} ({} bytes)", self.index, bytes.len()),
+        }
+    }
+}
+
+/// A camera authored into the model, already resolved to where it sits and what it sees.
+#[derive(Clone, Debug)]
+pub struct ModelCamera {
+    pub location: [f32; 3],
+    pub look_dir: [f32; 3],
+    /// Vertical field of view in radians, None for orthographic cameras.
+    pub yfov: Option<f32>,
+    pub z_near: f32,
+    pub z_far: Option<f32>,
+}
+
+impl Model {
+    pub fn cameras(&self) -> Vec<ModelCamera> {
+        let mut cameras = Vec::new();
+        for node in self.gltf.nodes() {
+            let camera = match node.camera() {
+                Some(camera) => camera,
+                None => continue,
+            };
+            let (translation, rotation, _) = node.transform().decomposed();
+            // glTF stores quaternions as [x, y, z, w]
+            let rotation = UnitQuaternion::from_quaternion(Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]));
+            // glTF cameras look down their local -Z
+            let look_dir = rotation * -Vector3::z();
+            let (yfov, z_near, z_far) = match camera.projection() {
+                Projection::Perspective(p) => (Some(p.yfov()), p.znear(), p.zfar()),
+                Projection::Orthographic(o) => (None, o.znear(), Some(o.zfar())),
+            };
+            cameras.push(ModelCamera {
+                location: translation,
+                look_dir: [look_dir.x, look_dir.y, look_dir.z],
+                yfov,
+                z_near,
+                z_far,
+            });
+        }
+        cameras
+    }
 }
 
 pub async fn build_fetcher(uri: String, window: &Window) -> CmcResult<Vec<u8>> {
     log::info!("Fetching {}", uri);
+    let mut delay = FETCH_BACKOFF_MS;
+    let mut attempt = 1;
+    loop {
+        let error = match fetch_once(&uri, window).await {
+            Ok(buffer) => return Ok(buffer),
+            Err(e) => e,
+        };
+        let retryable = match error {
+            CmcError::Http { status, .. } => status >= 500 || status == 408 || status == 429,
+            _ => true,
+        };
+        if !retryable || attempt == FETCH_ATTEMPTS {
+            return Err(CmcError::FetchFailed { uri, attempts: attempt, last: error.to_string() });
+        }
+        log::warn!("Fetching {} failed ({}), retrying in {}ms", uri, error, delay);
+        sleep(window, delay).await?;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+async fn sleep(window: &Window, ms: i32) -> CmcResult<()> {
+    let mut result = Ok(0);
+    let promise = Promise::new(&mut |resolve, _reject| {
+        result = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms);
+    });
+    result?;
+    JsFuture::from(promise).await?;
+    Ok(())
+}
+
+async fn fetch_once(uri: &str, window: &Window) -> CmcResult<Vec<u8>> {
     let mut opts = RequestInit::new();
     opts.method("GET");
     opts.mode(RequestMode::Cors);
 
-    let request = Request::new_with_str_and_init(&uri, &opts)?;
+    let request = Request::new_with_str_and_init(uri, &opts)?;
 
     let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
 
     let response: Response = resp_value.dyn_into()?;
+    if !response.ok() {
+        return Err(CmcError::Http { uri: uri.to_string(), status: response.status() });
+    }
 
     let raw_body = response.body().ok_or(CmcError::missing_val("Response body"))?;
 
+    let total = response.headers().get("Content-Length")?.and_then(|length| length.parse::<u64>().ok());
+    progress::update(uri, 0, total);
+
     let body = ReadableStream::from_raw(raw_body.dyn_into().map_err(|_| CmcError::conversion_failed("ReadableStream"))?);
-    let stream = body
-        .into_stream()
-        .map_ok(|js| {
-            Uint8Array::from(js).to_vec()
-        })
-        .map_err(|e| {
-            CmcError::from(e)
-        })
-        .try_collect::<Vec<Vec<u8>>>()
-        .map_ok(|v| {
-            v.into_iter().flatten().collect::<Vec<u8>>()
-        });
-    let buffer: Vec<u8> = stream.await?;
+    let mut stream = body.into_stream();
+    let mut buffer = Vec::with_capacity(total.unwrap_or(0) as usize);
+    while let Some(chunk) = stream.next().await {
+        buffer.extend(Uint8Array::from(chunk?).to_vec());
+        progress::update(uri, buffer.len() as u64, total);
+    }
+    pipeline_trace!(Fetched, uri, "{} bytes", buffer.len());
     Ok(buffer)
 }
 
+/// Every buffer or an error, geometry can't be built around a missing one.
 pub async fn load_buffers(gltf: &Gltf, server_root: &str, window: &Window) -> CmcResult<Vec<Vec<u8>>> {
     let mut output_buffers = Vec::new();
     for buffer in gltf.buffers() {
         // log::info!("Loading binary buffer: {:?}", buffer.name());
-        match buffer.source() {
-            BufSource::Uri(uri) => {
-                let uri = format!("{}/{}/{}",server_root, MODEL_DIR, uri);
-                if let Ok(buf) = build_fetcher(uri.clone(), window).await {
-                    output_buffers.insert(buffer.index(), buf);
-                } else {
-                    log::warn!("Failed to fetch buffer: {}", uri);
-                }
-            },
-            _ => log::warn!("Unhandled buffer type"),
-        }
+        let buf = match buffer.source() {
+            BufSource::Uri(uri) if uri.starts_with("data:") => decode_data_uri(uri)?,
+            BufSource::Uri(uri) => build_fetcher(format!("{}/{}/{}",server_root, MODEL_DIR, uri), window).await?,
+            BufSource::Bin => gltf.blob.clone()
+                .ok_or(CmcError::missing_val(format!("Binary chunk for buffer {}", buffer.index())))?,
+        };
+        output_buffers.insert(buffer.index(), buf);
     }
     Ok(output_buffers)
 }
 
-pub async fn load_images(gltf: &Gltf, server_root: &str, window: &Window) -> CmcResult<Vec<DynamicImage>> {
-    let mut output_buffers = Vec::new();
+pub fn deferred_images(gltf: &Gltf, buffers: &[Vec<u8>], server_root: &str) -> Vec<DeferredImage> {
+    let mut deferred = Vec::new();
     for image in gltf.images() {
         // log::info!("Loading image: {:?}", image.name());
-        match image.source() {
-            ImgSource::Uri{ uri, mime_type: _ } => {
-                let uri = format!("{}/{}/{}",server_root, MODEL_DIR, uri);
-                if let Ok(buf) = build_fetcher(uri.clone(), window).await {
-                    let image_buffer = image::load_from_memory(&buf[..])?;
-                    output_buffers.insert(image.index(), image_buffer);
+        let (source, mime_type) = match image.source() {
+            ImgSource::Uri{ uri, mime_type } => {
+                // Embedded images are decoded in place by load_image, the rest are on the server
+                let uri = if uri.starts_with("data:") {
+                    uri.to_string()
                 } else {
-                    log::warn!("Failed to fetch image: {}", uri);
+                    format!("{}/{}/{}",server_root, MODEL_DIR, uri)
+                };
+                (DeferredSource::Uri(uri), mime_type)
+            },
+            ImgSource::View{ view, mime_type } => {
+                // Each view names its own buffer, a .glb may carry more than the binary chunk
+                let bytes = buffers.get(view.buffer().index())
+                    .and_then(|buffer| buffer.get(view.offset()..view.offset() + view.length()));
+                match bytes {
+                    Some(bytes) => (DeferredSource::Embedded(bytes.to_vec()), Some(mime_type)),
+                    None => {
+                        log::warn!("Image {} is in buffer {} which isn't loaded", image.index(), view.buffer().index());
+                        continue;
+                    },
                 }
             },
-            _ => {
-                log::warn!("View image not handled!");
-            }
+        };
+        deferred.push(DeferredImage { index: image.index(), source, mime_type: mime_type.map(|m| m.to_string()) });
+    }
+    deferred
+}
+
+pub async fn load_deferred_image(image: &DeferredImage, window: &Window) -> CmcResult<DynamicImage> {
+    let declared = image.mime_type.as_deref();
+    match &image.source {
+        DeferredSource::Uri(uri) => load_image(uri, declared, window).await,
+        DeferredSource::Embedded(bytes) => decode_image(bytes, declared.and_then(format_from_mime)),
+    }
+}
+
+/// declared is the image's type if something said what it should be.
+pub async fn load_image(uri: &str, declared: Option<&str>, window: &Window) -> CmcResult<DynamicImage> {
+    let (buf, hint) = if let Some(header) = uri.strip_prefix("data:") {
+        // data:image/png;base64,... says its own type
+        let data_mime = header.split([';', ',']).next();
+        (decode_data_uri(uri)?, declared.or(data_mime).and_then(format_from_mime))
+    } else {
+        let hint = declared.and_then(format_from_mime).or_else(|| ImageFormat::from_path(uri).ok());
+        (build_fetcher(uri.to_string(), window).await?, hint)
+    };
+    decode_image(&buf, hint)
+}
+
+fn format_from_mime(mime: &str) -> Option<ImageFormat> {
+    match mime {
+        "image/png" => Some(ImageFormat::Png),
+        "image/jpeg" | "image/jpg" => Some(ImageFormat::Jpeg),
+        _ => None,
+    }
+}
+
+/// Decodes whatever the bytes turn out to be, the bytes win over hint (from the mime type or
+/// file extension), which is only tried when they aren't recognised. Formats that aren't built
+/// in (webp, ktx2) are an error naming the format instead of a bare decode failure.
+fn decode_image(bytes: &[u8], hint: Option<ImageFormat>) -> CmcResult<DynamicImage> {
+    let format = match (image::guess_format(bytes), hint) {
+        (Ok(format), _) => format,
+        (Err(_), Some(hint)) => hint,
+        (Err(e), None) => return Err(CmcError::context("Unrecognised image data with no type to go on", e)),
+    };
+    image::load_from_memory_with_format(bytes, format)
+        .map_err(|e| CmcError::context(format!("Decoding {:?} image", format), e))
+}
+
+/// Bytes of a base64 data uri such as data:application/octet-stream;base64,AAAA, the way
+/// Blender embeds buffers and images in a .gltf.
+fn decode_data_uri(uri: &str) -> CmcResult<Vec<u8>> {
+    let comma = uri.find(',')
+        .ok_or(CmcError::conversion_failed("Data uri without a comma"))?;
+    let (header, data) = (&uri[..comma], &uri[comma + 1..]);
+    if !header.ends_with(";base64") {
+        return Err(CmcError::conversion_failed(format!("Data uri that isn't base64: {}", header)));
+    }
+    Ok(base64::decode(data)?)
+}
+
+/// The json of a .gltf, or the json chunk of a .glb.
+fn json_chunk(raw: &[u8]) -> Cow<'_, [u8]> {
+    if raw.starts_with(b"glTF") {
+        match Glb::from_slice(raw) {
+            Ok(glb) => glb.json,
+            Err(_) => Cow::Borrowed(&[]),
         }
+    } else {
+        Cow::Borrowed(raw)
     }
-    Ok(output_buffers)
 }
 
+/// gltf 0.15 doesn't model KHR_texture_transform, so it is read straight from the json.
+pub fn texture_transforms(raw: &[u8]) -> HashMap<usize, Matrix3<f32>> {
+    let mut transforms = HashMap::new();
+    let json: serde_json::Value = match serde_json::from_slice(&json_chunk(raw)) {
+        Ok(json) => json,
+        Err(_) => return transforms,
+    };
+    let materials = match json["materials"].as_array() {
+        Some(materials) => materials,
+        None => return transforms,
+    };
+    for (index, material) in materials.iter().enumerate() {
+        let transform = &material["pbrMetallicRoughness"]["baseColorTexture"]["extensions"]["KHR_texture_transform"];
+        if transform.is_null() {
+            continue;
+        }
+        if !transform["texCoord"].is_null() {
+            log::warn!("KHR_texture_transform texCoord override on material {} ignored", index);
+        }
+        let pair = |key: &str, default: f32| -> [f32; 2] {
+            let value = &transform[key];
+            [
+                value[0].as_f64().map(|v| v as f32).unwrap_or(default),
+                value[1].as_f64().map(|v| v as f32).unwrap_or(default),
+            ]
+        };
+        let offset = pair("offset", 0.);
+        let scale = pair("scale", 1.);
+        let rotation = transform["rotation"].as_f64().unwrap_or(0.) as f32;
+        let (sin, cos) = rotation.sin_cos();
+        let translation = Matrix3::new(
+            1., 0., offset[0],
+            0., 1., offset[1],
+            0., 0., 1.,
+        );
+        let rotation = Matrix3::new(
+            cos, sin, 0.,
+            -sin, cos, 0.,
+            0., 0., 1.,
+        );
+        let scale = Matrix3::new(
+            scale[0], 0., 0.,
+            0., scale[1], 0.,
+            0., 0., 1.,
+        );
+        transforms.insert(index, translation * rotation * scale);
+    }
+    transforms
+}
@@ -0,0 +1,143 @@
+use crate::error::{CmcError, CmcResult};
+use std::collections::HashMap;
+
+/// A Wavefront .obj file, one mesh per o (or g) statement. Materials aren't read, every
+/// object draws in plain white.
+pub struct ObjModel {
+    pub name: String,
+    pub objects: Vec<ObjObject>,
+}
+
+/// Triangles ready for a vertex buffer, each v/vt/vn combination is one vertex.
+#[derive(Default)]
+pub struct ObjObject {
+    pub name: String,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u16>,
+    vertex_lookup: HashMap<(usize, Option<usize>, Option<usize>), u16>,
+}
+
+impl ObjObject {
+    fn named(name: &str) -> Self {
+        Self { name: name.to_string(), ..Default::default() }
+    }
+
+    fn push(&mut self, key: Option<(usize, Option<usize>, Option<usize>)>, position: [f32; 3], normal: [f32; 3], tex_coord: [f32; 2]) -> CmcResult<u16> {
+        if let Some(index) = key.and_then(|key| self.vertex_lookup.get(&key)) {
+            return Ok(*index);
+        }
+        if self.positions.len() > u16::MAX as usize {
+            return Err(CmcError::conversion_failed(format!("{}: over {} vertices", self.name, u16::MAX)));
+        }
+        let index = self.positions.len() as u16;
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.tex_coords.push(tex_coord);
+        if let Some(key) = key {
+            self.vertex_lookup.insert(key, index);
+        }
+        Ok(index)
+    }
+}
+
+/// A face corner's v/vt/vn as indices into the file's lists so far, negative ones count back.
+fn corner(token: &str, counts: (usize, usize, usize)) -> CmcResult<(usize, Option<usize>, Option<usize>)> {
+    let resolve = |value: Option<&str>, count: usize| -> CmcResult<Option<usize>> {
+        let value = match value {
+            Some(value) if !value.is_empty() => value,
+            _ => return Ok(None),
+        };
+        let index: i64 = value.parse()
+            .map_err(|_| CmcError::conversion_failed(format!("Face index {}", value)))?;
+        let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(CmcError::missing_val(format!("Face index {} of {}", index, count)));
+        }
+        Ok(Some(resolved as usize))
+    };
+    let mut parts = token.split('/');
+    let position = resolve(parts.next(), counts.0)?
+        .ok_or(CmcError::missing_val(format!("Position in face corner {}", token)))?;
+    let tex_coord = resolve(parts.next(), counts.1)?;
+    let normal = resolve(parts.next(), counts.2)?;
+    Ok((position, tex_coord, normal))
+}
+
+fn floats<'a, I: Iterator<Item = &'a str>>(values: I, line: &str) -> CmcResult<Vec<f32>> {
+    values.map(|v| v.parse::<f32>().map_err(|_| CmcError::conversion_failed(format!("Obj line {}", line)))).collect()
+}
+
+pub fn parse_obj(name: &str, text: &str) -> CmcResult<ObjModel> {
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+    let mut objects = vec![ObjObject::named(name)];
+    for line in text.lines() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let v = floats(tokens.take(3), line)?;
+                positions.push([*v.first().unwrap_or(&0.), *v.get(1).unwrap_or(&0.), *v.get(2).unwrap_or(&0.)]);
+            },
+            Some("vn") => {
+                let n = floats(tokens.take(3), line)?;
+                normals.push([*n.first().unwrap_or(&0.), *n.get(1).unwrap_or(&0.), *n.get(2).unwrap_or(&0.)]);
+            },
+            Some("vt") => {
+                // Obj puts v = 0 at the bottom of the image, glTF and the shader at the top
+                let t = floats(tokens.take(2), line)?;
+                tex_coords.push([*t.first().unwrap_or(&0.), 1. - *t.get(1).unwrap_or(&0.)]);
+            },
+            Some("o") | Some("g") => {
+                let object_name = match tokens.collect::<Vec<&str>>().join(" ") {
+                    object_name if object_name.is_empty() => name.to_string(),
+                    object_name => object_name,
+                };
+                let current = objects.last_mut().unwrap();
+                if current.indices.is_empty() {
+                    current.name = object_name;
+                } else {
+                    objects.push(ObjObject::named(&object_name));
+                }
+            },
+            Some("f") => {
+                let counts = (positions.len(), tex_coords.len(), normals.len());
+                let corners = tokens.map(|token| corner(token, counts)).collect::<CmcResult<Vec<_>>>()?;
+                if corners.len() < 3 {
+                    return Err(CmcError::missing_val(format!("Three corners in face {}", line)));
+                }
+                let object = objects.last_mut().unwrap();
+                // Polygons are fanned out from their first corner
+                for i in 1..corners.len() - 1 {
+                    let triangle = [corners[0], corners[i], corners[i + 1]];
+                    let flat_normal = if triangle.iter().any(|c| c.2.is_none()) {
+                        let [a, b, c] = [positions[triangle[0].0], positions[triangle[1].0], positions[triangle[2].0]];
+                        let (u, v) = ([b[0] - a[0], b[1] - a[1], b[2] - a[2]], [c[0] - a[0], c[1] - a[1], c[2] - a[2]]);
+                        let n = [u[1] * v[2] - u[2] * v[1], u[2] * v[0] - u[0] * v[2], u[0] * v[1] - u[1] * v[0]];
+                        let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt().max(f32::EPSILON);
+                        Some([n[0] / length, n[1] / length, n[2] / length])
+                    } else {
+                        None
+                    };
+                    for &(position, tex_coord, normal) in triangle.iter() {
+                        // Corners given a flat normal belong to their face only, so they aren't shared
+                        let key = flat_normal.map_or(Some((position, tex_coord, normal)), |_| None);
+                        let normal = normal.map_or_else(|| flat_normal.unwrap(), |n| normals[n]);
+                        let tex_coord = tex_coord.map_or([0., 0.], |t| tex_coords[t]);
+                        let index = object.push(key, positions[position], normal, tex_coord)?;
+                        object.indices.push(index);
+                    }
+                }
+            },
+            Some("mtllib") | Some("usemtl") => log::debug!("{}: obj materials aren't supported, ignoring {}", name, line),
+            _ => (),
+        }
+    }
+    objects.retain(|object| !object.indices.is_empty());
+    if objects.is_empty() {
+        return Err(CmcError::missing_val(format!("Faces in {}", name)));
+    }
+    Ok(ObjModel { name: name.to_string(), objects })
+}
@@ -0,0 +1,30 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// Global rather than on the client, the models download before the client exists.
+    static ref PROGRESS: Mutex<HashMap<String, FileProgress>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FileProgress {
+    pub loaded: u64,
+    /// From Content-Length, None when the server doesn't send it or it was the compressed size.
+    pub total: Option<u64>,
+}
+
+pub fn update(uri: &str, loaded: u64, total: Option<u64>) {
+    let total = total.filter(|total| *total >= loaded);
+    PROGRESS.lock().unwrap().insert(uri.to_string(), FileProgress { loaded, total });
+}
+
+/// Every file fetched so far, finished ones included.
+pub fn snapshot() -> Vec<(String, FileProgress)> {
+    let mut files: Vec<(String, FileProgress)> = PROGRESS.lock().unwrap()
+        .iter()
+        .map(|(uri, progress)| (uri.clone(), *progress))
+        .collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    files
+}
@@ -0,0 +1,74 @@
+use nalgebra::{Matrix4, Point3, Vector3};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_slices(min: &[f32], max: &[f32]) -> Option<Self> {
+        if min.len() < 3 || max.len() < 3 {
+            return None;
+        }
+        Some(Self::new(Point3::new(min[0], min[1], min[2]), Point3::new(max[0], max[1], max[2])))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).norm() / 2.
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Point3::from(self.min.coords.zip_map(&other.min.coords, f32::min)),
+            Point3::from(self.max.coords.zip_map(&other.max.coords, f32::max)),
+        )
+    }
+
+    /// Distance along dir (assumed normalized) to where the ray enters the box, 0 if it starts inside.
+    pub fn ray_intersection(&self, origin: &Point3<f32>, dir: &Vector3<f32>) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = f32::MAX;
+        for axis in 0..3 {
+            if dir[axis].abs() < f32::EPSILON {
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t1 = (self.min[axis] - origin[axis]) / dir[axis];
+            let t2 = (self.max[axis] - origin[axis]) / dir[axis];
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+
+    /// Box enclosing all eight corners after the transform, so it only ever grows under rotation.
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Aabb {
+        let mut min = Vector3::repeat(f32::MAX);
+        let mut max = Vector3::repeat(f32::MIN);
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            let corner = transform.transform_point(&corner);
+            min = min.zip_map(&corner.coords, f32::min);
+            max = max.zip_map(&corner.coords, f32::max);
+        }
+        Aabb::new(Point3::from(min), Point3::from(max))
+    }
+}
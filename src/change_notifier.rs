@@ -0,0 +1,61 @@
+use crate::{shape::Shape, uid::Uid};
+use js_sys::Function;
+use nalgebra::Vector3;
+use std::collections::HashMap;
+use wasm_bindgen::JsValue;
+
+/// Objects have to move further than this before it counts as a change.
+const MOVE_THRESHOLD: f32 = 0.01;
+/// At most one notification per this many ms, however busy the world is.
+const DEBOUNCE_MS: f32 = 250.;
+
+/// Tells the host when the set of objects or their placement changed, for autosave and the like.
+pub struct ChangeNotifier {
+    callback: Option<Function>,
+    dirty: bool,
+    positions: HashMap<Uid, Vector3<f32>>,
+    last_fired: f32,
+}
+
+impl ChangeNotifier {
+    pub fn new() -> Self {
+        Self {
+            callback: None,
+            dirty: false,
+            positions: HashMap::new(),
+            last_fired: f32::MIN,
+        }
+    }
+
+    pub fn set_callback(&mut self, callback: Option<Function>) {
+        self.callback = callback;
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Called once at the end of each frame, now is the frame time in ms.
+    pub fn end_frame(&mut self, shapes: &HashMap<Uid, Shape>, now: f32) -> Result<(), JsValue> {
+        let callback = match &self.callback {
+            Some(callback) => callback,
+            None => return Ok(()),
+        };
+        let moved = shapes.len() != self.positions.len() || shapes.iter().any(|(uid, shape)| {
+            match self.positions.get(uid) {
+                Some(last) => (shape.entity.transform.translation - last).norm() > MOVE_THRESHOLD,
+                None => true,
+            }
+        });
+        if !(self.dirty || moved) || now - self.last_fired < DEBOUNCE_MS {
+            return Ok(());
+        }
+        self.positions = shapes.iter()
+            .map(|(uid, shape)| (*uid, shape.entity.transform.translation))
+            .collect();
+        self.dirty = false;
+        self.last_fired = now;
+        callback.call0(&JsValue::NULL)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlContextAttributes;
+
+/// WebGL context attributes, fixed once the client has made its context.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct ContextOptions {
+    /// Multisampled edges, turning it off is cheaper on weak GPUs.
+    pub antialias: bool,
+    /// Lets the page show through where the canvas is transparent.
+    pub alpha: bool,
+    /// Keeps the last frame around after it is shown, for reading the canvas back from JS.
+    pub preserve_drawing_buffer: bool,
+    /// Asks for WebGL2, falling back to WebGL1 where the browser has none. Drawing still goes
+    /// through the WebGL1 calls, WebGL2 only lifts limits such as repeating NPOT textures.
+    pub webgl2: bool,
+}
+
+#[wasm_bindgen]
+impl ContextOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            antialias: true,
+            alpha: true,
+            preserve_drawing_buffer: false,
+            webgl2: false,
+        }
+    }
+}
+
+impl ContextOptions {
+    pub fn attributes(&self) -> WebGlContextAttributes {
+        let attributes = WebGlContextAttributes::new();
+        attributes.set_antialias(self.antialias);
+        attributes.set_alpha(self.alpha);
+        attributes.set_preserve_drawing_buffer(self.preserve_drawing_buffer);
+        attributes
+    }
+}
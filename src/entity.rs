@@ -1,17 +1,19 @@
-use nalgebra::Vector3;
+use crate::transform::Transform;
+use nalgebra::{Matrix4, UnitQuaternion, Vector3};
 
 pub struct Entity {
-    pub location: Vector3<f32>,
-    pub rotation: Vector3<f32>,
+    pub transform: Transform,
     pub velocity: Vector3<f32>,
     pub rotation_rate: Vector3<f32>,
 }
 
 impl Entity {
+    /// rot and rot_rate are scaled axis, the axis to spin around with the angle as its length.
     pub fn new(loc: Vector3<f32>, rot: Vector3<f32>, vel: Vector3<f32>, rot_rate: Vector3<f32>) -> Self {
+        let mut transform = Transform::from_translation(loc);
+        transform.rotation = UnitQuaternion::new(rot);
         Entity {
-            location: loc,
-            rotation: rot,
+            transform,
             velocity: vel,
             rotation_rate: rot_rate,
         }
@@ -24,13 +26,16 @@ impl Entity {
     pub fn new_at(loc: Vector3<f32>) -> Self {
         Entity::new(loc, Vector3::zeros(), Vector3::zeros(), Vector3::zeros())
     }
+
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        self.transform.to_matrix()
+    }
 }
 
 pub fn set_rotation(entity: &mut Entity, new_rot: Vector3<f32>) {
-    entity.rotation = new_rot;
+    entity.transform.rotation = UnitQuaternion::new(new_rot);
 }
 
-#[allow(unused)]
 pub fn set_rot_rate(entity: &mut Entity, new_rate: Vector3<f32>) {
     entity.rotation_rate = new_rate;
 }
@@ -38,7 +43,7 @@ pub fn set_rot_rate(entity: &mut Entity, new_rate: Vector3<f32>) {
 pub fn update(entity: &mut Entity, elapsed: f32) {
     let elapsed = elapsed / 1000.;
     let delta_loc = entity.velocity * elapsed;
-    entity.location = delta_loc + entity.location;
-    let delta_rot = entity.rotation_rate * elapsed;
-    entity.rotation = delta_rot + entity.rotation;
+    entity.transform.translation = delta_loc + entity.transform.translation;
+    let delta_rot = UnitQuaternion::new(entity.rotation_rate * elapsed);
+    entity.transform.rotation = delta_rot * entity.transform.rotation;
 }
@@ -32,6 +32,34 @@ pub enum CmcError {
         #[from]
         error: image::ImageError,
     },
+    #[error("Base64 error: {error}")]
+    Base64 {
+        #[from]
+        error: base64::DecodeError,
+    },
+    #[error("HTTP {status} fetching {uri}")]
+    Http {
+        uri: String,
+        status: u16,
+    },
+    #[error("Gave up on {uri} after {attempts} attempts: {last}")]
+    FetchFailed {
+        uri: String,
+        attempts: u32,
+        last: String,
+    },
+    /// Anything the other variants don't describe, with what caused it when there is one.
+    #[error("{msg}")]
+    Other {
+        msg: String,
+        #[source]
+        source: Option<Box<dyn std::error::Error>>,
+    },
+    #[error("Json error: {error}")]
+    Json {
+        #[from]
+        error: serde_json::Error,
+    },
 }
 
 impl CmcError {
@@ -42,6 +70,15 @@ impl CmcError {
     pub fn conversion_failed<S: AsRef<str>>(msg: S) -> Self {
         Self::ConversionFail(msg.as_ref().to_string())
     }
+
+    pub fn other<S: AsRef<str>>(msg: S) -> Self {
+        Self::Other { msg: msg.as_ref().to_string(), source: None }
+    }
+
+    /// msg says what was being done, the error it ran into stays reachable through source().
+    pub fn context<S: AsRef<str>, E: std::error::Error + 'static>(msg: S, source: E) -> Self {
+        Self::Other { msg: format!("{}: {}", msg.as_ref(), source), source: Some(Box::new(source)) }
+    }
 }
 
 impl From<CmcError> for JsValue {
@@ -0,0 +1,40 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use lazy_static::lazy_static;
+
+/// Oldest reports are dropped past this, in case nothing is taking them.
+const MAX_REPORTED: usize = 64;
+
+lazy_static! {
+    static ref REPORTED: Mutex<VecDeque<ErrorMsg>> = Mutex::new(VecDeque::new());
+}
+
+/// An error that was handled where it happened but that the user should still hear about.
+#[derive(Clone, Debug)]
+pub struct ErrorMsg {
+    pub file: &'static str,
+    pub line: u32,
+    pub msg: String,
+}
+
+pub fn report(file: &'static str, line: u32, msg: String) {
+    log::error!("{}:{}: {}", file, line, msg);
+    let mut reported = REPORTED.lock().unwrap();
+    if reported.len() == MAX_REPORTED {
+        reported.pop_front();
+    }
+    reported.push_back(ErrorMsg { file, line, msg });
+}
+
+/// Everything reported since the last take, oldest first.
+pub fn take() -> Vec<ErrorMsg> {
+    REPORTED.lock().unwrap().drain(..).collect()
+}
+
+/// report_error!("format", args) logs the message and queues it for take_errors, with the
+/// file and line it came from.
+macro_rules! report_error {
+    ($($arg:tt)+) => {
+        $crate::error_report::report(file!(), line!(), format!($($arg)+))
+    };
+}
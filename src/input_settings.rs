@@ -0,0 +1,21 @@
+#[derive(Clone, Debug)]
+pub struct InputSettings {
+    /// Radians of camera turn per pixel of mouse movement.
+    pub rotate: f32,
+    /// World units moved per second while a movement key is held.
+    pub pan: f32,
+    /// Radians of field of view per wheel pixel.
+    pub zoom: f32,
+    pub invert_y: bool,
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            rotate: 0.01,
+            pan: 7.5,
+            zoom: 0.001,
+            invert_y: false,
+        }
+    }
+}
@@ -1,9 +1,12 @@
+use crate::scene::PresetView;
+
 #[derive(Clone, Debug)]
 pub struct KeyState {
     pub forward: bool,
     pub backward: bool,
     pub left: bool,
     pub right: bool,
+    pub preset_view: Option<PresetView>,
 }
 
 impl KeyState {
@@ -13,24 +16,33 @@ impl KeyState {
             backward: false,
             left: false,
             right: false,
+            preset_view: None,
         }
     }
 
-    pub fn set_key(&mut self, key: String) {
+    /// Movement keys stay held from keydown until keyup, preset views fire once on keydown.
+    pub fn set_key(&mut self, key: String, pressed: bool) {
         match key.as_ref() {
-            "KeyW" => self.forward = true,
-            "KeyS" => self.backward = true,
-            "KeyA" => self.left = true,
-            "KeyD" => self.right = true,
-            k => log::warn!("Unhandled key: {}", k),
+            "KeyW" => self.forward = pressed,
+            "KeyS" => self.backward = pressed,
+            "KeyA" => self.left = pressed,
+            "KeyD" => self.right = pressed,
+            "Digit1" if pressed => self.preset_view = Some(PresetView::Front),
+            "Digit2" if pressed => self.preset_view = Some(PresetView::Top),
+            "Digit3" if pressed => self.preset_view = Some(PresetView::Side),
+            "Digit4" if pressed => self.preset_view = Some(PresetView::Iso),
+            k if pressed => log::warn!("Unhandled key: {}", k),
+            _ => (),
         }
     }
 
+    /// Lets go of everything, for when keyups can't be seen any more.
     pub fn clear(&mut self) {
         self.forward = false;
         self.backward = false;
         self.left = false;
         self.right = false;
+        self.preset_view = None;
     }
 }
 
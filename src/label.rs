@@ -0,0 +1,66 @@
+use crate::{bounds::Aabb, scene::Scene};
+use nalgebra::Point3;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Document, HtmlCanvasElement, HtmlElement};
+
+/// Text drawn over the canvas that follows a point in the world.
+pub struct Label {
+    element: HtmlElement,
+}
+
+impl Label {
+    pub fn new(document: &Document, text: &str) -> Result<Self, JsValue> {
+        let element = document.create_element("div")?.dyn_into::<HtmlElement>()?;
+        element.set_inner_text(text);
+        let style = element.style();
+        style.set_property("position", "absolute")?;
+        style.set_property("pointer-events", "none")?;
+        style.set_property("transform", "translate(-50%, -100%)")?;
+        style.set_property("color", "white")?;
+        style.set_property("font-family", "monospace")?;
+        style.set_property("display", "none")?;
+        document.body().ok_or(JsValue::from_str("No body!"))?.append_child(&element)?;
+        Ok(Self { element })
+    }
+
+    pub fn set_text(&self, text: &str) {
+        self.element.set_inner_text(text);
+    }
+
+    /// Places the label over anchor, hiding it when off screen or when an occluder is in the way.
+    pub fn update(&self, canvas: &HtmlCanvasElement, scene: &Scene, anchor: &Point3<f32>, occluders: &[Aabb]) -> Result<(), JsValue> {
+        let style = self.element.style();
+        let screen = scene.world_to_screen(anchor)
+            .filter(|p| p[0] >= 0. && p[1] >= 0. && p[0] <= canvas.width() as f32 && p[1] <= canvas.height() as f32);
+        let screen = match screen {
+            Some(screen) if !is_occluded(scene, anchor, occluders) => screen,
+            _ => return style.set_property("display", "none"),
+        };
+        let rect = canvas.get_bounding_client_rect();
+        let window = web_sys::window().ok_or(JsValue::from_str("No window"))?;
+        let left = rect.left() + window.scroll_x()? + screen[0] as f64;
+        let top = rect.top() + window.scroll_y()? + screen[1] as f64;
+        style.set_property("left", &format!("{}px", left))?;
+        style.set_property("top", &format!("{}px", top))?;
+        style.set_property("display", "block")
+    }
+}
+
+impl Drop for Label {
+    fn drop(&mut self) {
+        self.element.remove();
+    }
+}
+
+fn is_occluded(scene: &Scene, anchor: &Point3<f32>, occluders: &[Aabb]) -> bool {
+    let eye = scene.get_eye();
+    let to_anchor = anchor - eye;
+    let distance = to_anchor.norm();
+    if distance < f32::EPSILON {
+        return false;
+    }
+    let dir = to_anchor / distance;
+    occluders.iter()
+        .filter_map(|b| b.ray_intersection(&eye, &dir))
+        .any(|t| t > 0. && t < distance)
+}
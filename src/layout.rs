@@ -0,0 +1,32 @@
+use crate::error::{CmcError, CmcResult};
+use nalgebra::Vector3;
+
+/// Most points a single layout will place.
+pub const MAX_LAYOUT_POINTS: u32 = 10_000;
+
+/// rows x cols points on the xz plane, starting at origin and stepping spacing along +x and +z.
+pub fn grid_positions(rows: u32, cols: u32, spacing: f32, origin: Vector3<f32>) -> CmcResult<Vec<Vector3<f32>>> {
+    let count = rows.checked_mul(cols)
+        .filter(|count| *count <= MAX_LAYOUT_POINTS)
+        .ok_or(CmcError::conversion_failed(format!("Grid of {} x {}, the limit is {} points", rows, cols, MAX_LAYOUT_POINTS)))?;
+    let mut positions = Vec::with_capacity(count as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            positions.push(origin + Vector3::new(col as f32 * spacing, 0., row as f32 * spacing));
+        }
+    }
+    Ok(positions)
+}
+
+/// count points evenly around a circle on the xz plane.
+pub fn ring_positions(count: u32, radius: f32, center: Vector3<f32>) -> CmcResult<Vec<Vector3<f32>>> {
+    if count > MAX_LAYOUT_POINTS {
+        return Err(CmcError::conversion_failed(format!("Ring of {}, the limit is {} points", count, MAX_LAYOUT_POINTS)));
+    }
+    Ok((0..count)
+        .map(|i| {
+            let angle = 2. * std::f32::consts::PI * i as f32 / count as f32;
+            center + Vector3::new(radius * angle.cos(), 0., radius * angle.sin())
+        })
+        .collect())
+}
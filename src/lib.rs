@@ -1,21 +1,51 @@
-use crate::{scene::Scene, entity::Entity, shape::Shape, error::CmcError, render::RenderCache, light::{Attenuator, Light}};
+use crate::{assets::{ArrivedImage, FailedAsset, ModelCamera}, bounds::Aabb, scene::{PresetView, ProjectionMode, Scene, ToneMap}, entity::Entity, shape::{capsule_ratio, Shape, ShapeDescriptor}, error::{CmcError, CmcResult}, render::{Capture, DepthOfField, Environment, GobImage, PostProcess, RenderCache, RenderSettings, ShapeRenderer}, light::{Attenuator, Light, ATTENUATION_PRESETS}};
 use log::{trace, debug};
 use wasm_bindgen::JsCast;
 use wasm_bindgen::JsValue;
 use wasm_bindgen::prelude::*;
-use web_sys::{Document, Element, Event, EventTarget, HtmlCanvasElement, HtmlInputElement, WebGlRenderingContext as WebGL};
-use js_sys::Function;
-use nalgebra::Vector3;
+use web_sys::{Document, Element, Event, EventTarget, HtmlCanvasElement, HtmlInputElement, HtmlSelectElement, WebGlRenderingContext as WebGL};
+use js_sys::{Array, Function, Object, Reflect};
+use nalgebra::{Matrix4, Quaternion, UnitQuaternion, Vector3};
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::RwLock;
+use input_settings::InputSettings;
 use key_state::KeyState;
+use transform::Transform;
+use uid::{get_new_uid, Uid};
+use tween::{Easing, Tween};
+use label::Label;
+use change_notifier::ChangeNotifier;
+pub use context_options::ContextOptions;
+use recording::{Command, RecordedCommand, Recorder};
 
 const GIT_VERSION: &str = git_version::git_version!();
 const RUST_CANVAS: &str = "rustCanvas";
+const DEFAULT_SPAWN_RENDERER: &str = "Cube_glb";
+const SPAWN_SELECT: &str = "spawnRenderer";
+const BENCHMARK_GRID: u32 = 10;
+const BENCHMARK_FRAME_MS: f32 = 1000. / 60.;
+/// Where the benchmark camera sits, looking at the middle of its grid.
+const BENCHMARK_EYE: [f32; 3] = [0., 25., 35.];
+/// How far step_once moves things along, about one frame of the page's 30fps throttle.
+const SINGLE_STEP_MS: f32 = 1000. / 30.;
+/// Longest single motion step, longer frames are split up.
+const MAX_STEP_MS: f32 = 1000. / 30.;
+/// Beyond this many steps the rest of a long gap (a backgrounded tab) is dropped.
+const MAX_SUBSTEPS: u32 = 4;
+/// How long the camera takes to swing onto a tracked object, after which it stays locked on.
+const TRACKING_TURN_MS: f32 = 400.;
 
+#[macro_use]
+mod pipeline_trace;
+#[macro_use]
+mod error_report;
 mod key_state;
+mod input_settings;
+mod bounds;
+mod change_notifier;
+mod context_options;
 mod entity;
 mod error;
 mod render;
@@ -24,31 +54,73 @@ mod shape;
 mod state;
 mod assets;
 mod light;
+mod recording;
+mod layout;
+mod label;
+mod transform;
+mod tween;
+mod uid;
 
 #[wasm_bindgen]
 pub struct CmcClient {
     web_gl: WebGL,
-    #[allow(dead_code)]
     rendercache: RenderCache,
-    shapes: Vec<Shape>,
+    shapes: HashMap<Uid, Shape>,
     lights: Vec<Light>,
     callbacks: HashMap<String, Rc<Closure<dyn FnMut(Event)>>>,
     document: Rc<Document>,
     canvas: Rc<HtmlCanvasElement>,
     scene: Arc<RwLock<Scene>>,
     key_state: Arc<RwLock<KeyState>>,
+    input_settings: Arc<RwLock<InputSettings>>,
+    /// Shared with the spawn picker on the page.
+    spawn_renderer: Arc<RwLock<String>>,
+    spawn_points: Arc<RwLock<Vec<[f32; 2]>>>,
+    model_cameras: HashMap<String, Vec<ModelCamera>>,
+    arrived_images: Arc<RwLock<Vec<ArrivedImage>>>,
+    images_finished: Arc<RwLock<bool>>,
+    /// Models and images that gave up loading, filled in as they do.
+    failed_assets: Arc<RwLock<Vec<FailedAsset>>>,
+    render_settings: RenderSettings,
+    labels: HashMap<Uid, Label>,
+    change_notifier: ChangeNotifier,
+    recorder: Recorder,
+    environment: Option<Environment>,
+    /// Built the first time depth of field is turned on.
+    post_process: Option<PostProcess>,
+    /// Last rotation the X/Y/Z sliders applied, so spins aren't reset every frame.
+    slider_rotation: Vector3<f32>,
+    /// Units per second squared, pulling on every unparented object that isn't frozen.
+    gravity: Vector3<f32>,
+    /// While set objects hold still, everything else (camera, commands, edits) carries on.
+    paused: bool,
+    /// Steps asked for by step_once, taken one per update while paused.
+    pending_steps: u32,
+    /// Object the camera keeps turning to face, see set_target.
+    camera_target: Option<CameraTracking>,
+    arrived_environment: Arc<RwLock<Option<image::DynamicImage>>>,
+}
+
+/// The camera turning from where it looked when tracking started onto target, eased by turn.
+#[derive(Clone)]
+struct CameraTracking {
+    target: Uid,
+    from: Vector3<f32>,
+    turn: Tween<f32>,
 }
 
 #[wasm_bindgen]
 impl CmcClient {
+    /// options are the WebGL context attributes, browser defaults when left out.
     #[wasm_bindgen(constructor)]
-    pub async fn new() -> Result<CmcClient, JsValue> {
+    pub async fn new(options: Option<ContextOptions>) -> Result<CmcClient, JsValue> {
         let window = web_sys::window().expect("no global `window` exists");
         let location = window.location();
         let document: Document = window.document().expect("should have a document on window");
-        let body = document.body().expect("No body!");
+        let body = document.body().ok_or(CmcError::missing_val("Document body"))?;
 
-        let models = assets::load_models(location.origin()?, &window).await?;
+        let loaded = assets::load_models(location.origin()?, &window).await?;
+        let models = loaded.gltf;
 
         let (label, slider) = create_slider(&document, "X", 0.0..360.0, 0.0, |x| state::update_shape_rotation(0, x))?;
         body.append_child(&label)?;
@@ -66,6 +138,17 @@ impl CmcClient {
         body.append_child(&label)?;
         body.append_child(&slider)?;
 
+        let input_settings = Arc::new(RwLock::new(InputSettings::default()));
+        let settings_clone = input_settings.clone();
+        let (label, slider) = create_slider(&document, "Look sensitivity", 1.0..50.0, 10.0, move |x| settings_clone.write().unwrap().rotate = x as f32 / 1000.)?;
+        body.append_child(&label)?;
+        body.append_child(&slider)?;
+
+        let settings_clone = input_settings.clone();
+        let (label, slider) = create_slider(&document, "Zoom sensitivity", 1.0..10.0, 1.0, move |x| settings_clone.write().unwrap().zoom = x as f32 / 1000.)?;
+        body.append_child(&label)?;
+        body.append_child(&slider)?;
+
         let (label, slider) = create_slider(&document, "X", -10.0..10.0, 0.0, |x| state::update_light_location(0, x))?;
         body.append_child(&label)?;
         body.append_child(&slider)?;
@@ -79,13 +162,25 @@ impl CmcClient {
         body.append_child(&slider)?;
         let document = Rc::new(document);
         let canvas: Rc<HtmlCanvasElement> = Rc::new(setup_canvas(&document)?);
-        let gl = setup_gl_context(&canvas, true)?;
-        let rendercache = render::build_rendercache(&gl, &models).expect("Failed to create rendercache");
+        let gl = setup_gl_context(&canvas, &options.unwrap_or_default(), true)?;
+        let rendercache = render::build_rendercache(&gl, &models, &loaded.obj)
+            .map_err(|e| CmcError::context("Creating the render cache", e))?;
+        let model_cameras: HashMap<String, Vec<ModelCamera>> = models.iter()
+            .map(|model| (model.name.clone(), model.cameras()))
+            .collect();
+        // Geometry is drawable now, textures follow as they download
+        let deferred_images = models.iter()
+            .flat_map(|model| model.deferred_images.iter().map(move |image| (model.name.clone(), image.clone())))
+            .collect();
+        let arrived_images = Arc::new(RwLock::new(Vec::new()));
+        let images_finished = Arc::new(RwLock::new(false));
+        let failed_assets = Arc::new(RwLock::new(loaded.failed));
+        wasm_bindgen_futures::spawn_local(assets::stream_images(deferred_images, arrived_images.clone(), images_finished.clone(), failed_assets.clone()));
         log::info!("Available shapes");
         for key in rendercache.shape_renderers.keys() {
             log::info!("{}", key);
         }
-        let mut shapes = Vec::new();
+        let mut shapes = HashMap::new();
         let mut entity_locs = Vec::new();
         for i in 0..4 {
             for j in 0..4 {
@@ -96,8 +191,10 @@ impl CmcClient {
         }
         for loc in entity_locs.iter() {
             let entity = Entity::new_at(Vector3::new(loc[0], loc[1], loc[2]));
-            let cube_renderer = rendercache.get_shaperenderer("Cube_glb").expect("Failed to get renderer");
-            shapes.push(Shape::new(cube_renderer, entity));
+            let cube_renderer = rendercache.get_shaperenderer("Cube_glb")
+                .or_else(|| rendercache.get_shaperenderer("prim_box"))
+                .ok_or(CmcError::missing_val("Renderer for the starting cubes"))?;
+            shapes.insert(get_new_uid(), Shape::new(cube_renderer, entity));
         }
 
         let scene = Arc::new(RwLock::new(Scene::new([-3., 2., 3.], 640., 480.)));
@@ -106,6 +203,18 @@ impl CmcClient {
             Light::new_point([5.,0.,0.], [1., 1., 1.], 5.0, Attenuator::new_7m()),
             Light::new_point([-5.,0.,0.], [1.,1.,1.], 5.0, Attenuator::new_7m()),
         ];
+        let spawn_renderer = match rendercache.get_shaperenderer(DEFAULT_SPAWN_RENDERER) {
+            Some(_) => DEFAULT_SPAWN_RENDERER.to_string(),
+            None => "prim_box".to_string(),
+        };
+        let mut renderer_names: Vec<String> = rendercache.shape_renderers.keys().cloned().collect();
+        renderer_names.sort();
+        let spawn_renderer = Arc::new(RwLock::new(spawn_renderer));
+        let spawn_clone = spawn_renderer.clone();
+        let (label, select) = create_select(&document, "Spawn", &renderer_names, &spawn_renderer.read().unwrap(), move |name| *spawn_clone.write().unwrap() = name)?;
+        select.set_id(SPAWN_SELECT);
+        body.append_child(&label)?;
+        body.append_child(&select)?;
         let mut client = CmcClient {
             web_gl: gl,
             rendercache,
@@ -116,6 +225,25 @@ impl CmcClient {
             canvas,
             scene,
             key_state: Arc::new(RwLock::new(KeyState::new())),
+            input_settings,
+            spawn_renderer,
+            spawn_points: Arc::new(RwLock::new(Vec::new())),
+            model_cameras,
+            arrived_images,
+            images_finished,
+            failed_assets,
+            render_settings: RenderSettings::new(),
+            labels: HashMap::new(),
+            change_notifier: ChangeNotifier::new(),
+            recorder: Recorder::new(),
+            environment: None,
+            post_process: None,
+            slider_rotation: Vector3::zeros(),
+            gravity: Vector3::zeros(),
+            paused: false,
+            pending_steps: 0,
+            camera_target: None,
+            arrived_environment: Arc::new(RwLock::new(None)),
         };
 
         attach_mouse_onclick_handler(&mut client)?;
@@ -125,6 +253,10 @@ impl CmcClient {
     }
 
     pub fn update(&mut self, elapsed_time: f32, height: f32, width: f32) -> Result<(), JsValue> {
+        self.recorder.next_frame();
+        for command in self.recorder.due() {
+            self.apply_command(command)?;
+        }
         let state = state::get_curr();
         self.lights[0].set_location(state.light_location);
         let delta_t = state::update(elapsed_time, height, width);
@@ -135,32 +267,991 @@ impl CmcClient {
             rotations[2] as f32 * std::f32::consts::PI / 180.,
         );
         let key_state = self.key_state.read().unwrap().clone();
-        {
-            let mut key_state = self.key_state.write().unwrap();
-            key_state.clear();
-        }
+        self.key_state.write().unwrap().preset_view = None;
         {
             let mut scene = self.scene.write().unwrap();
             scene.update_aspect(width, height);
-            scene.update_from_key_state(&key_state);
+            self.web_gl.viewport(0, 0, width as i32, height as i32);
+            scene.update_from_key_state(&key_state, &self.input_settings.read().unwrap(), delta_t);
+        }
+        if let Some(view) = key_state.preset_view {
+            self.apply_preset_view(view);
+        }
+
+        let arrived_images: Vec<ArrivedImage> = self.arrived_images.write().unwrap().drain(..).collect();
+        for arrived in arrived_images {
+            let image = GobImage::from(&arrived.image);
+            // One bad upload shouldn't strand the images queued behind it
+            if let Err(e) = self.rendercache.texture_arrived(&self.web_gl, &arrived.model, arrived.index, &image) {
+                report_error!("Image {} of {} failed to upload: {:?}", arrived.index, arrived.model, e);
+            }
         }
 
-        for shape in self.shapes.iter_mut() {
-            crate::entity::update(&mut shape.entity, delta_t);
-            crate::entity::set_rotation(&mut shape.entity, rotations);
+        let arrived_environment = self.arrived_environment.write().unwrap().take();
+        if let Some(image) = arrived_environment {
+            self.environment = Some(Environment::new(&self.web_gl, &GobImage::from(&image))?);
+            self.render_settings.environment = true;
         }
+
+        let spawn_points: Vec<[f32; 2]> = self.spawn_points.write().unwrap().drain(..).collect();
+        for point in spawn_points {
+            // A bad click shouldn't cost the whole frame
+            if let Err(e) = self.spawn_at_screen(point[0], point[1]) {
+                report_error!("Spawn at ({}, {}) failed: {:?}", point[0], point[1], e);
+            }
+        }
+
+        let sliders_moved = rotations != self.slider_rotation;
+        self.slider_rotation = rotations;
+        // update keeps running while paused, so delta_t stays one frame long and resuming doesn't jump
+        let motion_t = if !self.paused {
+            Some(delta_t)
+        } else if self.pending_steps > 0 {
+            self.pending_steps -= 1;
+            Some(SINGLE_STEP_MS)
+        } else {
+            None
+        };
+        let (steps, step_t) = match motion_t {
+            Some(motion_t) if motion_t > 0. => {
+                let steps = ((motion_t / MAX_STEP_MS).ceil() as u32).clamp(1, MAX_SUBSTEPS);
+                (steps, (motion_t / steps as f32).min(MAX_STEP_MS))
+            },
+            _ => (0, 0.),
+        };
+        let gravity = self.gravity;
+        for shape in self.shapes.values_mut().filter(|s| !s.is_frozen()) {
+            for _ in 0..steps {
+                if shape.parent.is_none() {
+                    shape.entity.velocity += gravity * step_t / 1000.;
+                }
+                crate::entity::update(&mut shape.entity, step_t);
+            }
+            if sliders_moved {
+                crate::entity::set_rotation(&mut shape.entity, rotations);
+            }
+        }
+        if let Some(target) = self.camera_target.as_ref().map(|tracking| tracking.target) {
+            if self.shapes.contains_key(&target) {
+                let world = self.world_matrix(&target);
+                let tracking = self.camera_target.as_mut().unwrap();
+                let amount = tracking.turn.advance(delta_t.max(0.));
+                self.scene.write().unwrap().turn_towards(tracking.from, [world[(0, 3)], world[(1, 3)], world[(2, 3)]], amount);
+            } else {
+                self.camera_target = None;
+            }
+        }
+        self.change_notifier.end_frame(&self.shapes, elapsed_time)?;
         Ok(())
     }
 
+    /// callback is called with no arguments, at most every quarter second, after objects are
+    /// added, removed, swapped or moved. Pass undefined to stop.
+    pub fn on_world_changed(&mut self, callback: Option<Function>) {
+        self.change_notifier.set_callback(callback);
+    }
+
     pub fn render(&self) {
+        // A hidden canvas reports 0x0, just wait for the next update to give it a size
+        if !self.scene.read().unwrap().has_area() {
+            return;
+        }
+        if let Err(e) = self.update_labels() {
+            report_error!("Failed to place labels: {:?}", e);
+        }
         self.web_gl.clear(WebGL::COLOR_BUFFER_BIT | WebGL::DEPTH_BUFFER_BIT);
+        if let Some(environment) = &self.environment {
+            environment.bind(&self.web_gl);
+        }
         let scene = {
             self.scene.read().unwrap().clone()
         };
 
-        for shape in self.shapes.iter() {
-            shape.render(&self.web_gl, &scene, &self.lights)
+        let post = match (&self.post_process, &self.render_settings.depth_of_field) {
+            (Some(post), Some(dof)) => match post.begin(&self.web_gl, self.canvas.width() as i32, self.canvas.height() as i32) {
+                Ok(()) => Some((post, dof)),
+                Err(e) => {
+                    log::warn!("Drawing without depth of field: {}", e);
+                    None
+                },
+            },
+            _ => None,
+        };
+        self.draw_scene(&scene);
+        if let Some((post, dof)) = post {
+            post.finish(&self.web_gl, scene.clip_planes(), dof);
+        }
+    }
+
+    fn draw_scene(&self, scene: &Scene) {
+        let (transparent, opaque): (Vec<_>, Vec<_>) = self.shapes.iter()
+            .filter(|(_, shape)| shape.visible)
+            .partition(|(_, shape)| shape.renderer().is_transparent());
+        for (uid, shape) in opaque {
+            pipeline_trace!(ObjectDrawn, shape.renderer().model, "uid {} as {}", uid, shape.renderer().name);
+            shape.render(&self.web_gl, scene, &self.lights, &self.world_matrix(uid), &self.render_settings)
+        }
+        if !transparent.is_empty() {
+            // Back to front so nearer surfaces blend over further ones
+            let eye = scene.get_eye();
+            let mut transparent: Vec<(f32, &Uid, &Shape)> = transparent.into_iter()
+                .map(|(uid, shape)| {
+                    let world = self.world_matrix(uid);
+                    let location = nalgebra::Point3::new(world[(0, 3)], world[(1, 3)], world[(2, 3)]);
+                    (nalgebra::distance_squared(&eye, &location), uid, shape)
+                })
+                .collect();
+            transparent.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+            // Blending is already on from setup_gl_context, only depth writes change
+            self.web_gl.depth_mask(false);
+            for (_, uid, shape) in transparent {
+                pipeline_trace!(ObjectDrawn, shape.renderer().model, "uid {} as {} (transparent)", uid, shape.renderer().name);
+                shape.render(&self.web_gl, scene, &self.lights, &self.world_matrix(uid), &self.render_settings)
+            }
+            self.web_gl.depth_mask(true);
+        }
+        if self.render_settings.show_axes {
+            for (uid, shape) in self.shapes.iter().filter(|(_, shape)| shape.visible) {
+                // Reach just past the mesh so the lines aren't buried inside it
+                let length = shape.renderer().bounds().map(|b| b.radius() * 1.2).unwrap_or(1.);
+                let axes_mat = self.world_matrix(uid) * Matrix4::new_scaling(length);
+                self.rendercache.axes.render(&self.web_gl, scene, &axes_mat);
+            }
+        }
+    }
+
+    /// 1 front, 2 top, 3 side, 4 iso; the same as the number keys.
+    pub fn set_preset_view(&mut self, index: u32) -> Result<(), JsValue> {
+        let view = PresetView::from_index(index)
+            .ok_or(CmcError::missing_val(format!("Preset view {}", index)))?;
+        self.apply_preset_view(view);
+        Ok(())
+    }
+
+    /// Moves the camera to the index'th camera authored into the named model.
+    pub fn use_model_camera(&mut self, model: String, index: usize) -> Result<(), JsValue> {
+        let camera = self.model_cameras.get(&model)
+            .and_then(|cameras| cameras.get(index))
+            .ok_or(CmcError::missing_val(format!("Camera {} in {}", index, model)))?;
+        let mut scene = self.scene.write().unwrap();
+        scene.move_absolute(camera.location);
+        let target = [
+            camera.location[0] + camera.look_dir[0],
+            camera.location[1] + camera.look_dir[1],
+            camera.location[2] + camera.look_dir[2],
+        ];
+        scene.look_at(target);
+        match camera.yfov {
+            Some(yfov) => scene.set_lens(yfov, camera.z_near, camera.z_far.unwrap_or(scene::Z_FAR)),
+            None => log::warn!("Orthographic camera in {}, only using its placement", model),
+        }
+        Ok(())
+    }
+
+    /// Whether ContextOptions.webgl2 got a WebGL2 context, false when it fell back.
+    pub fn is_webgl2(&self) -> bool {
+        render::is_webgl2(&self.web_gl)
+    }
+
+    /// True once every startup asset has loaded (or given up) and been handed to the renderers.
+    pub fn assets_ready(&self) -> bool {
+        *self.images_finished.read().unwrap() && self.arrived_images.read().unwrap().is_empty()
+    }
+
+    /// [{name, error}] for every model or image that gave up loading so far.
+    pub fn failed_assets(&self) -> Result<JsValue, JsValue> {
+        let failed = Array::new();
+        for asset in self.failed_assets.read().unwrap().iter() {
+            let entry = Object::new();
+            Reflect::set(&entry, &"name".into(), &asset.name.as_str().into())?;
+            Reflect::set(&entry, &"error".into(), &asset.error.as_str().into())?;
+            failed.push(&entry);
+        }
+        Ok(failed.into())
+    }
+
+    /// Logs every object command from now on, starting with the objects that already exist.
+    pub fn start_recording(&mut self) {
+        let mut uids: Vec<&Uid> = self.shapes.keys().collect();
+        uids.sort();
+        let mut initial = Vec::new();
+        for uid in uids.iter() {
+            let entity = &self.shapes[uid].entity;
+            initial.push(Command::AddObject {
+                uid: (**uid).into(),
+                renderer: self.shapes[uid].renderer().name.clone(),
+                location: entity.transform.translation.into(),
+            });
+            if entity.velocity != Vector3::zeros() {
+                initial.push(Command::SetVelocity { uid: (**uid).into(), velocity: entity.velocity.into() });
+            }
+            if entity.rotation_rate != Vector3::zeros() {
+                initial.push(Command::SetSpin { uid: (**uid).into(), rate: entity.rotation_rate.into() });
+            }
+            if entity.transform.rotation != UnitQuaternion::identity() || entity.transform.scale != Vector3::repeat(1.) {
+                initial.push(rotation_scale_command(**uid, &entity.transform));
+            }
+        }
+        for uid in uids.iter() {
+            if let Some(parent) = self.shapes[uid].parent {
+                initial.push(Command::SetParent { child: (**uid).into(), parent: parent.into() });
+            }
+        }
+        self.recorder.start(initial);
+    }
+
+    /// The log as JSON, ready for replay. Empty when nothing was being recorded.
+    pub fn stop_recording(&mut self) -> Result<String, JsValue> {
+        let commands = self.recorder.stop().unwrap_or_default();
+        Ok(serde_json::to_string(&commands).map_err(CmcError::from)?)
+    }
+
+    /// Clears the world and plays a stop_recording log back at the frames it was recorded on.
+    pub fn replay(&mut self, json: String) -> Result<(), JsValue> {
+        let commands: Vec<RecordedCommand> = serde_json::from_str(&json).map_err(CmcError::from)?;
+        for (uid, _) in self.shapes.drain() {
+            Uid::free(uid);
+        }
+        self.labels.clear();
+        self.camera_target = None;
+        self.change_notifier.mark_dirty();
+        self.recorder.replay(commands);
+        Ok(())
+    }
+
+    /// Names accepted by add_object and set_spawn_renderer, sorted for display.
+    pub fn list_renderers(&self) -> Array {
+        let mut names: Vec<&String> = self.rendercache.shape_renderers.keys().collect();
+        names.sort();
+        names.into_iter().map(|name| JsValue::from_str(name)).collect()
+    }
+
+    /// Sets which winding counts as the front of a renderer's triangles, for assets exported clockwise.
+    pub fn set_front_face(&mut self, renderer: String, clockwise: bool) -> Result<(), JsValue> {
+        self.rendercache.shape_renderers.get(&renderer)
+            .ok_or(CmcError::missing_val(format!("Renderer {}", renderer)))?
+            .set_clockwise(clockwise);
+        Ok(())
+    }
+
+    /// Returns the new object's uid.
+    pub fn add_object(&mut self, renderer: String, x: f32, y: f32, z: f32) -> Result<u32, JsValue> {
+        let uid = self.spawn_shape(&renderer, Vector3::new(x, y, z))?;
+        Ok(uid.into())
+    }
+
+    /// Adds a primitive of a given size, shape is JSON such as {"type": "Cuboid", "half_extents": [1, 0.5, 1]},
+    /// {"type": "Ball", "radius": 0.5} or {"type": "Capsule", "half_height": 0.5, "radius": 0.25}.
+    /// Sizes have to be above 0, a capsule's half_height is rounded to a quarter of its radius.
+    /// Returns the new object's uid.
+    pub fn add_shape(&mut self, shape: String, x: f32, y: f32, z: f32) -> Result<u32, JsValue> {
+        let shape: ShapeDescriptor = serde_json::from_str(&shape).map_err(CmcError::from)?;
+        let uid = self.spawn_described(&shape, Vector3::new(x, y, z))?;
+        Ok(uid.into())
+    }
+
+    /// Like add_object but turned and sized. rotation is [x, y, z] euler angles in degrees applied
+    /// roll, pitch then yaw, scale is per axis and has to be above 0. Nothing is added if any value is off.
+    pub fn add_object_transformed(&mut self, renderer: String, x: f32, y: f32, z: f32, rotation: &[f32], scale: &[f32]) -> Result<u32, JsValue> {
+        let location = vector3_from(&[x, y, z], "Location")?;
+        let rotation = vector3_from(rotation, "Rotation")?;
+        let scale = vector3_from(scale, "Scale")?;
+        if scale.iter().any(|s| *s <= 0.) {
+            return Err(CmcError::conversion_failed(format!("Scale {:?}", scale.as_slice())).into());
+        }
+        let uid = self.spawn_shape(&renderer, location)?;
+        if let Some(shape) = self.shapes.get_mut(&uid) {
+            shape.entity.transform.rotation = UnitQuaternion::from_euler_angles(rotation.x.to_radians(), rotation.y.to_radians(), rotation.z.to_radians());
+            shape.entity.transform.scale = scale;
+            self.recorder.record(rotation_scale_command(uid, &shape.entity.transform));
+        }
+        Ok(uid.into())
+    }
+
+    /// Like add_object but already moving, velocity is [x, y, z] in units per second.
+    pub fn add_object_with_velocity(&mut self, renderer: String, x: f32, y: f32, z: f32, velocity: &[f32]) -> Result<u32, JsValue> {
+        let velocity = vector3_from(velocity, "Velocity")?;
+        let uid = self.spawn_shape(&renderer, Vector3::new(x, y, z))?;
+        if let Some(shape) = self.shapes.get_mut(&uid) {
+            shape.entity.velocity = velocity;
+        }
+        self.recorder.record(Command::SetVelocity { uid: uid.into(), velocity: velocity.into() });
+        Ok(uid.into())
+    }
+
+    /// Children are let go of, removing an unknown uid only logs a warning. The uid is given
+    /// to a later object once enough others have been removed, so drop it after this.
+    pub fn remove_object(&mut self, uid: u32) {
+        let recorded = Command::RemoveObject { uid };
+        let uid = Uid::from(uid);
+        if self.shapes.remove(&uid).is_none() {
+            log::warn!("Tried to remove unknown object {}", uid);
+            return;
+        }
+        self.recorder.record(recorded);
+        self.labels.remove(&uid);
+        if self.camera_target.as_ref().map(|tracking| tracking.target) == Some(uid) {
+            self.camera_target = None;
+        }
+        Uid::free(uid);
+        self.change_notifier.mark_dirty();
+        for shape in self.shapes.values_mut().filter(|s| s.parent == Some(uid)) {
+            shape.parent = None;
+        }
+    }
+
+    /// Changes what an object looks like while keeping its uid and motion.
+    pub fn set_object_renderer(&mut self, uid: u32, renderer: String) -> Result<(), JsValue> {
+        let uid = Uid::from(uid);
+        let new_renderer = self.rendercache.get_shaperenderer(&renderer)
+            .ok_or(CmcError::missing_val(format!("Renderer {}", renderer)))?;
+        let shape = self.shapes.get_mut(&uid)
+            .ok_or(CmcError::missing_val(format!("Object {}", uid)))?;
+        shape.set_renderer(new_renderer);
+        self.change_notifier.mark_dirty();
+        self.recorder.record(Command::SetObjectRenderer { uid: uid.into(), renderer });
+        Ok(())
+    }
+
+    /// The object's glTF extras, undefined when its asset has none.
+    pub fn get_object_extras(&self, uid: u32) -> Result<JsValue, JsValue> {
+        let uid = Uid::from(uid);
+        let shape = self.shapes.get(&uid)
+            .ok_or(CmcError::missing_val(format!("Object {}", uid)))?;
+        match &shape.renderer().extras {
+            Some(extras) => js_sys::JSON::parse(&extras.to_string()),
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+
+    /// Floats text over an object, an empty string removes it.
+    pub fn set_object_label(&mut self, uid: u32, text: String) -> Result<(), JsValue> {
+        let uid = Uid::from(uid);
+        if text.is_empty() {
+            self.labels.remove(&uid);
+            return Ok(());
+        }
+        if !self.shapes.contains_key(&uid) {
+            return Err(CmcError::missing_val(format!("Object {}", uid)).into());
+        }
+        match self.labels.get(&uid) {
+            Some(label) => label.set_text(&text),
+            None => {
+                self.labels.insert(uid, Label::new(&self.document, &text)?);
+            },
+        }
+        Ok(())
+    }
+
+    /// Makes child's transform relative to parent so it follows the parent around.
+    pub fn set_parent(&mut self, child: u32, parent: u32) -> Result<(), JsValue> {
+        let (child, parent) = (Uid::from(child), Uid::from(parent));
+        if !self.shapes.contains_key(&parent) {
+            return Err(CmcError::missing_val(format!("Parent object {}", parent)).into());
+        }
+        let mut ancestor = Some(parent);
+        while let Some(uid) = ancestor {
+            if uid == child {
+                return Err(CmcError::other(format!("Parenting {} to {} would make a cycle", child, parent)).into());
+            }
+            ancestor = self.shapes.get(&uid).and_then(|s| s.parent);
+        }
+        let shape = self.shapes.get_mut(&child)
+            .ok_or(CmcError::missing_val(format!("Object {}", child)))?;
+        shape.parent = Some(parent);
+        self.change_notifier.mark_dirty();
+        self.recorder.record(Command::SetParent { child: child.into(), parent: parent.into() });
+        Ok(())
+    }
+
+    pub fn clear_parent(&mut self, child: u32) -> Result<(), JsValue> {
+        let child = Uid::from(child);
+        let shape = self.shapes.get_mut(&child)
+            .ok_or(CmcError::missing_val(format!("Object {}", child)))?;
+        shape.parent = None;
+        self.change_notifier.mark_dirty();
+        self.recorder.record(Command::ClearParent { child: child.into() });
+        Ok(())
+    }
+
+    /// Adds to an object's velocity, in units per second (unit mass).
+    pub fn apply_impulse(&mut self, uid: u32, x: f32, y: f32, z: f32) -> Result<(), JsValue> {
+        let uid = Uid::from(uid);
+        let shape = self.shapes.get_mut(&uid)
+            .ok_or(CmcError::missing_val(format!("Object {}", uid)))?;
+        if shape.is_frozen() {
+            log::warn!("Object {} is frozen, ignoring impulse", uid);
+            return Ok(());
+        }
+        shape.entity.velocity += Vector3::new(x, y, z);
+        self.recorder.record(Command::SetVelocity { uid: uid.into(), velocity: shape.entity.velocity.into() });
+        Ok(())
+    }
+
+    /// Adds to an object's spin, in radians per second about each axis (unit inertia).
+    pub fn apply_torque_impulse(&mut self, uid: u32, x: f32, y: f32, z: f32) -> Result<(), JsValue> {
+        let uid = Uid::from(uid);
+        let shape = self.shapes.get_mut(&uid)
+            .ok_or(CmcError::missing_val(format!("Object {}", uid)))?;
+        if shape.is_frozen() {
+            log::warn!("Object {} is frozen, ignoring torque impulse", uid);
+            return Ok(());
+        }
+        shape.entity.rotation_rate += Vector3::new(x, y, z);
+        self.recorder.record(Command::SetSpin { uid: uid.into(), rate: shape.entity.rotation_rate.into() });
+        Ok(())
+    }
+
+    /// Keeps an object turning at a steady rate, in radians per second about each axis, with no
+    /// physics involved. Zero stops it. A frozen object keeps the rate for when it thaws.
+    pub fn set_object_spin(&mut self, uid: u32, x: f32, y: f32, z: f32) -> Result<(), JsValue> {
+        let rate = vector3_from(&[x, y, z], "Spin")?;
+        let uid = Uid::from(uid);
+        let shape = self.shapes.get_mut(&uid)
+            .ok_or(CmcError::missing_val(format!("Object {}", uid)))?;
+        shape.set_rotation_rate(rate);
+        self.recorder.record(Command::SetSpin { uid: uid.into(), rate: rate.into() });
+        Ok(())
+    }
+
+    /// Stops every object moving until unpaused. Objects can still be added, removed and edited.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        if !paused {
+            self.pending_steps = 0;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// While paused, moves everything along by one frame on the next update.
+    pub fn step_once(&mut self) {
+        if !self.paused {
+            log::warn!("step_once only does anything while paused");
+            return;
+        }
+        self.pending_steps += 1;
+    }
+
+    /// Off (zero) by default as nothing stops objects falling, takes effect from the next update.
+    pub fn set_gravity(&mut self, x: f32, y: f32, z: f32) {
+        self.gravity = Vector3::new(x, y, z);
+    }
+
+    pub fn get_gravity(&self) -> Vec<f32> {
+        self.gravity.as_slice().to_vec()
+    }
+
+    /// Pushes every object within radius of the center outwards, strength is the change in speed at
+    /// the center, fading to nothing at the edge. Frozen and parented objects are left alone.
+    /// Returns how many objects were pushed.
+    pub fn apply_radial_impulse(&mut self, x: f32, y: f32, z: f32, radius: f32, strength: f32) -> u32 {
+        if radius <= 0. {
+            return 0;
+        }
+        let center = Vector3::new(x, y, z);
+        let pushes: Vec<(Uid, Vector3<f32>)> = self.shapes.iter()
+            .filter(|(_, shape)| !shape.is_frozen() && shape.parent.is_none())
+            .filter_map(|(uid, shape)| {
+                let offset = shape.entity.transform.translation - center;
+                let distance = offset.norm();
+                if distance > radius {
+                    return None;
+                }
+                // Something sitting right on the center has no outward, send it up
+                let direction = if distance > f32::EPSILON { offset / distance } else { Vector3::y() };
+                Some((*uid, direction * strength * (1. - distance / radius)))
+            })
+            .collect();
+        for (uid, push) in pushes.iter() {
+            if let Some(shape) = self.shapes.get_mut(uid) {
+                shape.entity.velocity += push;
+                self.recorder.record(Command::SetVelocity { uid: (*uid).into(), velocity: shape.entity.velocity.into() });
+            }
+        }
+        pushes.len() as u32
+    }
+
+    /// Hidden objects keep moving and carrying their children, they just aren't drawn.
+    pub fn set_object_visible(&mut self, uid: u32, visible: bool) -> Result<(), JsValue> {
+        let uid = Uid::from(uid);
+        self.shapes.get_mut(&uid)
+            .ok_or(CmcError::missing_val(format!("Object {}", uid)))?
+            .visible = visible;
+        Ok(())
+    }
+
+    /// Holds one object still while everything else keeps moving, false lets it go as it was.
+    pub fn freeze_object(&mut self, uid: u32, frozen: bool) -> Result<(), JsValue> {
+        let uid = Uid::from(uid);
+        let shape = self.shapes.get_mut(&uid)
+            .ok_or(CmcError::missing_val(format!("Object {}", uid)))?;
+        if frozen {
+            shape.freeze();
+        } else {
+            shape.thaw();
+        }
+        Ok(())
+    }
+
+    /// Swings the camera onto an object and keeps it in view as it moves, until clear_target
+    /// or the object is removed. The camera stays where it is, only its direction follows.
+    /// easing is 0 linear, 1 ease in, 2 ease out or 3 ease in and out, the default.
+    pub fn set_target(&mut self, uid: u32, easing: Option<u32>) -> Result<(), JsValue> {
+        let easing = match easing {
+            Some(index) => Easing::from_index(index).ok_or(CmcError::missing_val(format!("Easing {}", index)))?,
+            None => Easing::EaseInOut,
+        };
+        let uid = Uid::from(uid);
+        if !self.shapes.contains_key(&uid) {
+            return Err(CmcError::missing_val(format!("Object {}", uid)).into());
+        }
+        let from = self.scene.read().unwrap().look_dir();
+        self.camera_target = Some(CameraTracking {
+            target: uid,
+            from,
+            turn: Tween::new(0., 1., TRACKING_TURN_MS, easing),
+        });
+        Ok(())
+    }
+
+    pub fn clear_target(&mut self) {
+        self.camera_target = None;
+    }
+
+    /// origin is [x, y, z], at most 10000 objects are placed.
+    pub fn spawn_grid(&mut self, renderer: String, rows: u32, cols: u32, spacing: f32, origin: &[f32]) -> Result<(), JsValue> {
+        for location in layout::grid_positions(rows, cols, spacing, vector3_from(origin, "Grid origin")?)? {
+            self.spawn_shape(&renderer, location)?;
+        }
+        Ok(())
+    }
+
+    /// center is [x, y, z], at most 10000 objects are placed.
+    pub fn spawn_ring(&mut self, renderer: String, count: u32, radius: f32, center: &[f32]) -> Result<(), JsValue> {
+        for location in layout::ring_positions(count, radius, vector3_from(center, "Ring center")?)? {
+            self.spawn_shape(&renderer, location)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a light like sunlight, direction is [x, y, z] the way the light travels and color is
+    /// [r, g, b] from 0 up. Returns the new light's index.
+    pub fn add_directional_light(&mut self, direction: &[f32], color: &[f32], intensity: f32) -> Result<usize, JsValue> {
+        let direction = vector3_from(direction, "Light direction")?;
+        let color = vector3_from(color, "Light color")?;
+        if direction.norm() < f32::EPSILON || color.iter().any(|c| *c < 0.) || !(intensity.is_finite() && intensity >= 0.) {
+            return Err(CmcError::conversion_failed(format!("Directional light {:?} {:?} {}", direction.as_slice(), color.as_slice(), intensity)).into());
+        }
+        self.lights.push(Light::new_directional(direction.into(), color.into(), intensity));
+        Ok(self.lights.len() - 1)
+    }
+
+    /// Ranges in meters accepted by set_light_range.
+    pub fn light_range_presets(&self) -> Array {
+        ATTENUATION_PRESETS.iter().map(|(range, _)| JsValue::from_f64(*range as f64)).collect()
+    }
+
+    /// Picks the attenuation preset that reaches at least range meters.
+    pub fn set_light_range(&mut self, index: usize, range: f32) -> Result<(), JsValue> {
+        self.set_light_attenuator(index, Attenuator::for_range(range))
+    }
+
+    pub fn set_light_attenuation(&mut self, index: usize, constant: f32, linear: f32, quadratic: f32) -> Result<(), JsValue> {
+        self.set_light_attenuator(index, Attenuator::new(constant, linear, quadratic))
+    }
+
+    /// Lights the scene from an equirectangular image (jpg or png, ideally power of two sides),
+    /// replacing any previous one once it has downloaded.
+    pub fn load_environment(&mut self, uri: String) {
+        wasm_bindgen_futures::spawn_local(assets::fetch_image_into(uri, self.arrived_environment.clone(), self.failed_assets.clone()));
+    }
+
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.render_settings.environment_intensity = intensity.max(0.);
+    }
+
+    /// Blurs what is away from focus_distance, more so with a bigger aperture.
+    /// Needs the WEBGL_depth_texture extension, errors without it.
+    pub fn set_depth_of_field(&mut self, enabled: bool, focus_distance: f32, aperture: f32) -> Result<(), JsValue> {
+        if !enabled {
+            self.render_settings.depth_of_field = None;
+            return Ok(());
+        }
+        if self.post_process.is_none() {
+            self.post_process = Some(PostProcess::new(&self.web_gl)?);
+        }
+        self.render_settings.depth_of_field = Some(DepthOfField { focus_distance, aperture: aperture.max(0.) });
+        Ok(())
+    }
+
+    /// {view, projection}, each a column major Float32Array of 16.
+    pub fn get_view_projection(&self) -> Result<JsValue, JsValue> {
+        let scene = self.scene.read().unwrap();
+        let matrices = Object::new();
+        Reflect::set(&matrices, &"view".into(), &js_sys::Float32Array::from(scene.get_view_as_vec().as_slice()))?;
+        Reflect::set(&matrices, &"projection".into(), &js_sys::Float32Array::from(scene.get_projection_as_vec().as_slice()))?;
+        Ok(matrices.into())
+    }
+
+    /// PNG bytes of one frame drawn at scale (1 to 4) times the canvas size and averaged back down.
+    /// Depth of field is left out of the capture.
+    pub fn screenshot(&self, scale: u32) -> Result<Vec<u8>, JsValue> {
+        let scene = self.scene.read().unwrap().clone();
+        if !scene.has_area() {
+            return Err(CmcError::missing_val("Canvas area for screenshot").into());
+        }
+        let canvas_size = (self.canvas.width() as i32, self.canvas.height() as i32);
+        let capture = Capture::new(&self.web_gl, canvas_size.0, canvas_size.1, scale)?;
+        capture.begin(&self.web_gl);
+        if let Some(environment) = &self.environment {
+            environment.bind(&self.web_gl);
+        }
+        self.draw_scene(&scene);
+        Ok(capture.finish(&self.web_gl, canvas_size)?)
+    }
+
+    /// The camera as JSON, to hand back to set_camera later.
+    pub fn get_camera(&self) -> Result<String, JsValue> {
+        Ok(self.scene.read().unwrap().to_json()?)
+    }
+
+    /// Puts back a camera from get_camera, its size gives way to the canvas on the next update.
+    pub fn set_camera(&mut self, json: String) -> Result<(), JsValue> {
+        let scene = Scene::from_json(&json)?;
+        *self.scene.write().unwrap() = scene;
+        Ok(())
+    }
+
+    /// Moves the camera amount units forward along its view, negative moves it back.
+    pub fn dolly_camera(&mut self, amount: f32) {
+        self.scene.write().unwrap().dolly(amount);
+    }
+
+    /// fov is vertical and in radians, clamped to 10 to 120 degrees.
+    pub fn set_perspective(&mut self, fov: f32) -> Result<(), JsValue> {
+        self.scene.write().unwrap().set_projection_mode(ProjectionMode::Perspective { fov })?;
+        Ok(())
+    }
+
+    /// Nothing nearer than near or further than far is drawn. near has to be above 0 and below far.
+    pub fn set_clip_planes(&mut self, near: f32, far: f32) -> Result<(), JsValue> {
+        self.scene.write().unwrap().set_clip_planes(near, far)?;
+        Ok(())
+    }
+
+    /// Flat projection showing height world units top to bottom, for CAD style views.
+    pub fn set_orthographic(&mut self, height: f32) -> Result<(), JsValue> {
+        self.scene.write().unwrap().set_projection_mode(ProjectionMode::Orthographic { height })?;
+        Ok(())
+    }
+
+    /// Light every surface gets even where no light reaches, 0.1 grey to start with.
+    pub fn set_ambient_light(&mut self, r: f32, g: f32, b: f32) {
+        self.scene.write().unwrap().set_ambient([r.max(0.), g.max(0.), b.max(0.)]);
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) -> Result<(), JsValue> {
+        self.scene.write().unwrap().set_exposure(exposure)?;
+        Ok(())
+    }
+
+    /// 0 none, 1 Reinhard, 2 ACES.
+    pub fn set_tone_map(&mut self, index: u32) -> Result<(), JsValue> {
+        let tone_map = ToneMap::from_index(index)
+            .ok_or(CmcError::missing_val(format!("Tone map {}", index)))?;
+        self.scene.write().unwrap().set_tone_map(tone_map);
+        Ok(())
+    }
+
+    /// Renders a fixed grid scene from a fixed camera, unpaused and ignoring held keys, for the given
+    /// number of frames and reports frame times in ms as {min, max, mean, p95}. The world, camera
+    /// and pause state are put back the way they were afterwards.
+    pub fn run_benchmark(&mut self, frames: u32) -> Result<JsValue, JsValue> {
+        let performance = web_sys::window()
+            .and_then(|w| w.performance())
+            .ok_or(CmcError::missing_val("window.performance"))?;
+        let saved_shapes = std::mem::take(&mut self.shapes);
+        // update drains these, hold them back for the real world to pick up afterwards
+        let saved_spawn_points = std::mem::take(&mut *self.spawn_points.write().unwrap());
+        let saved_images = std::mem::take(&mut *self.arrived_images.write().unwrap());
+        let saved_environment = self.arrived_environment.write().unwrap().take();
+        // Held keys and a pause would make the numbers depend on whoever called this
+        let saved_keys = std::mem::replace(&mut *self.key_state.write().unwrap(), KeyState::new());
+        let saved_paused = std::mem::replace(&mut self.paused, false);
+        let saved_steps = std::mem::take(&mut self.pending_steps);
+        let saved_target = self.camera_target.take();
+        // The benchmark grid is throwaway, keep it from looking like a world change
+        let saved_notifier = std::mem::replace(&mut self.change_notifier, ChangeNotifier::new());
+        let saved_recorder = std::mem::replace(&mut self.recorder, Recorder::new());
+        let app_state = state::get_curr();
+        let (height, width, start_time) = (app_state.canvas_height, app_state.canvas_width, app_state.time);
+        let mut benchmark_scene = Scene::new(BENCHMARK_EYE, width, height);
+        benchmark_scene.look_at([0., 0., 0.]);
+        let saved_scene = std::mem::replace(&mut *self.scene.write().unwrap(), benchmark_scene);
+
+        let spacing = 3.;
+        let offset = -spacing * (BENCHMARK_GRID - 1) as f32 / 2.;
+        let result = self.spawn_grid(DEFAULT_SPAWN_RENDERER.to_string(), BENCHMARK_GRID, BENCHMARK_GRID, spacing, &[offset, 0., offset])
+            .and_then(|_| {
+                let mut frame_times = Vec::with_capacity(frames as usize);
+                for frame in 0..frames {
+                    let begin = performance.now();
+                    self.update(start_time + frame as f32 * BENCHMARK_FRAME_MS, height, width)?;
+                    self.render();
+                    frame_times.push(performance.now() - begin);
+                }
+                Ok(frame_times)
+            });
+
+        for uid in std::mem::replace(&mut self.shapes, saved_shapes).keys() {
+            Uid::free(*uid);
+        }
+        self.change_notifier = saved_notifier;
+        self.recorder = saved_recorder;
+        *self.scene.write().unwrap() = saved_scene;
+        self.camera_target = saved_target;
+        self.paused = saved_paused;
+        self.pending_steps = saved_steps;
+        *self.key_state.write().unwrap() = saved_keys;
+        self.spawn_points.write().unwrap().splice(0..0, saved_spawn_points);
+        self.arrived_images.write().unwrap().splice(0..0, saved_images);
+        if saved_environment.is_some() {
+            *self.arrived_environment.write().unwrap() = saved_environment;
+        }
+        state::update(start_time, height, width);
+        let mut frame_times = result?;
+        if frame_times.is_empty() {
+            return Err(CmcError::missing_val("Benchmark frames").into());
+        }
+
+        frame_times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let mean = frame_times.iter().sum::<f64>() / frame_times.len() as f64;
+        let p95 = frame_times[((frame_times.len() - 1) as f64 * 0.95) as usize];
+        let stats = Object::new();
+        Reflect::set(&stats, &"min".into(), &frame_times[0].into())?;
+        Reflect::set(&stats, &"max".into(), &frame_times[frame_times.len() - 1].into())?;
+        Reflect::set(&stats, &"mean".into(), &mean.into())?;
+        Reflect::set(&stats, &"p95".into(), &p95.into())?;
+        Ok(stats.into())
+    }
+
+    /// rotate, pan and zoom have to be above 0, use invert_y to flip the vertical look.
+    pub fn set_input_settings(&mut self, rotate: f32, pan: f32, zoom: f32, invert_y: bool) -> Result<(), JsValue> {
+        let speeds = vector3_from(&[rotate, pan, zoom], "Input settings")?;
+        if speeds.iter().any(|speed| *speed <= 0.) {
+            return Err(CmcError::conversion_failed(format!("Input settings {:?}", speeds.as_slice())).into());
+        }
+        *self.input_settings.write().unwrap() = InputSettings { rotate, pan, zoom, invert_y };
+        Ok(())
+    }
+
+    /// On by default, turn it off for models with open or inside out geometry that isn't marked doubleSided.
+    pub fn set_face_culling(&mut self, enabled: bool) {
+        self.render_settings.cull_faces = enabled;
+    }
+
+    /// Draws mesh edges over the shaded surface, color components are 0 to 1.
+    pub fn set_wireframe_overlay(&mut self, enabled: bool, r: f32, g: f32, b: f32) {
+        self.render_settings.wireframe_overlay = enabled;
+        self.render_settings.wireframe_color = [r, g, b];
+    }
+
+    /// Edge width in pixels for the wireframe overlay.
+    pub fn set_wireframe_width(&mut self, width: f32) {
+        self.render_settings.wireframe_width = width.max(1.);
+    }
+
+    /// Gives one object its own wireframe, on even when the overlay is off. enabled false removes it.
+    pub fn set_object_wireframe(&mut self, uid: u32, enabled: bool, r: f32, g: f32, b: f32, width: f32) -> Result<(), JsValue> {
+        let uid = Uid::from(uid);
+        let shape = self.shapes.get_mut(&uid)
+            .ok_or(CmcError::missing_val(format!("Object {}", uid)))?;
+        shape.wireframe = if enabled { Some(([r, g, b], width.max(1.))) } else { None };
+        Ok(())
+    }
+
+    pub fn set_show_axes(&mut self, enabled: bool) {
+        self.render_settings.show_axes = enabled;
+    }
+
+    /// Soft darkening near the ground, call every frame to animate it. strength is clamped to 0 to 1,
+    /// height has to be above 0.
+    pub fn set_contact_shadow(&mut self, enabled: bool, strength: f32, height: f32) -> Result<(), JsValue> {
+        if !(strength.is_finite() && height.is_finite() && height > 0.) {
+            return Err(CmcError::conversion_failed(format!("Contact shadow strength {} height {}", strength, height)).into());
+        }
+        self.render_settings.contact_shadow = enabled;
+        self.render_settings.contact_shadow_strength = strength.clamp(0., 1.);
+        self.render_settings.contact_shadow_height = height;
+        Ok(())
+    }
+
+    /// Fails on a name list_renderers doesn't have, keeping the previous one.
+    pub fn set_spawn_renderer(&mut self, renderer: String) -> Result<(), JsValue> {
+        if self.rendercache.get_shaperenderer(&renderer).is_none() {
+            return Err(CmcError::missing_val(format!("Renderer {}", renderer)).into());
+        }
+        if let Some(select) = self.document.get_element_by_id(SPAWN_SELECT).and_then(|e| e.dyn_into::<HtmlSelectElement>().ok()) {
+            select.set_value(&renderer);
+        }
+        *self.spawn_renderer.write().unwrap() = renderer;
+        Ok(())
+    }
+
+    /// Spawns the current spawn renderer where the pixel under (x, y) meets the ground plane.
+    pub fn spawn_at_screen(&mut self, x: f32, y: f32) -> Result<(), JsValue> {
+        let point = self.scene.read().unwrap().screen_to_ground(x, y);
+        match point {
+            Some(point) => {
+                let renderer = self.spawn_renderer.read().unwrap().clone();
+                self.spawn_shape(&renderer, point.coords)?;
+            },
+            None => log::warn!("Nothing under ({}, {}) to spawn on", x, y),
         }
+        Ok(())
+    }
+
+    /// Uid of the nearest object under the pixel (x, y), origin top left. Objects are hit by their
+    /// bounding boxes, so a click just off a round mesh can still land on it.
+    pub fn pick(&self, x: f32, y: f32) -> Option<u32> {
+        let (origin, dir) = self.scene.read().unwrap().screen_to_world_ray(x, y)?;
+        self.shapes.iter()
+            .filter(|(_, shape)| shape.visible)
+            .filter_map(|(uid, shape)| {
+                let bounds = shape.world_bounds(&self.world_matrix(uid))?;
+                bounds.ray_intersection(&origin, &dir).map(|distance| (distance, *uid))
+            })
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(_, uid)| uid.into())
+    }
+
+    fn apply_preset_view(&mut self, view: PresetView) {
+        let bounds = self.scene_bounds()
+            .unwrap_or(Aabb::new([-1., -1., -1.].into(), [1., 1., 1.].into()));
+        self.scene.write().unwrap().set_preset_view(view, &bounds);
+    }
+
+    fn update_labels(&self) -> Result<(), JsValue> {
+        if self.labels.is_empty() {
+            return Ok(());
+        }
+        let scene = self.scene.read().unwrap();
+        let worlds: HashMap<Uid, Matrix4<f32>> = self.shapes.keys()
+            .map(|uid| (*uid, self.world_matrix(uid)))
+            .collect();
+        for (uid, label) in self.labels.iter() {
+            let world = match worlds.get(uid) {
+                Some(world) => world,
+                None => continue,
+            };
+            let anchor = world.transform_point(&nalgebra::Point3::origin());
+            let occluders: Vec<Aabb> = self.shapes.iter()
+                .filter(|(other, shape)| *other != uid && shape.visible)
+                .filter_map(|(other, shape)| shape.world_bounds(&worlds[other]))
+                .collect();
+            label.update(&self.canvas, &scene, &anchor, &occluders)?;
+        }
+        Ok(())
+    }
+
+    /// Parent world transforms composed down to this object's own.
+    fn world_matrix(&self, uid: &Uid) -> Matrix4<f32> {
+        let mut world = Matrix4::identity();
+        let mut current = self.shapes.get(uid);
+        while let Some(shape) = current {
+            world = shape.entity.model_matrix() * world;
+            current = shape.parent.and_then(|parent| self.shapes.get(&parent));
+        }
+        world
+    }
+
+    fn scene_bounds(&self) -> Option<Aabb> {
+        self.shapes.iter()
+            .filter_map(|(uid, shape)| shape.world_bounds(&self.world_matrix(uid)))
+            .fold(None, |acc: Option<Aabb>, b| Some(acc.map_or(b, |acc| acc.union(&b))))
+    }
+
+    fn spawn_shape(&mut self, name: &str, location: Vector3<f32>) -> CmcResult<Uid> {
+        let renderer = self.rendercache.get_shaperenderer(name)
+            .ok_or(CmcError::missing_val(format!("Renderer {}", name)))?;
+        let uid = self.insert_shape(renderer, Entity::new_at(location));
+        self.recorder.record(Command::AddObject { uid: uid.into(), renderer: name.to_string(), location: location.into() });
+        Ok(uid)
+    }
+
+    fn spawn_described(&mut self, shape: &ShapeDescriptor, location: Vector3<f32>) -> CmcResult<Uid> {
+        shape.validate()?;
+        let (name, scale) = shape.renderer_and_scale();
+        if let ShapeDescriptor::Capsule { half_height, radius } = shape {
+            if self.rendercache.get_shaperenderer(&name).is_none() {
+                let capsule = render::build_capsule(&self.web_gl, &name, capsule_ratio(*half_height, *radius), 1.)?;
+                self.rendercache.add_shaperenderer(&name, capsule);
+            }
+        }
+        let renderer = self.rendercache.get_shaperenderer(&name)
+            .ok_or(CmcError::missing_val(format!("Renderer {}", name)))?;
+        let mut entity = Entity::new_at(location);
+        entity.transform.scale = scale;
+        let uid = self.insert_shape(renderer, entity);
+        self.recorder.record(Command::AddShape { uid: uid.into(), shape: shape.clone(), location: location.into() });
+        Ok(uid)
+    }
+
+    /// Gives the new object a uid and adds it to the world, recording how it was made is up to the caller.
+    fn insert_shape(&mut self, renderer: Rc<ShapeRenderer>, entity: Entity) -> Uid {
+        let uid = get_new_uid();
+        pipeline_trace!(ObjectSpawned, renderer.model, "uid {} as {}", uid, renderer.name);
+        self.shapes.insert(uid, Shape::new(renderer, entity));
+        self.change_notifier.mark_dirty();
+        uid
+    }
+
+    fn apply_command(&mut self, command: Command) -> Result<(), JsValue> {
+        match command {
+            Command::AddObject { uid, renderer, location } => {
+                let actual = self.spawn_shape(&renderer, Vector3::from(location))?;
+                self.recorder.map_uid(uid, actual.into());
+            },
+            Command::AddShape { uid, shape, location } => {
+                let actual = self.spawn_described(&shape, Vector3::from(location))?;
+                self.recorder.map_uid(uid, actual.into());
+            },
+            Command::SetVelocity { uid, velocity } => {
+                let uid = Uid::from(self.recorder.uid(uid));
+                if let Some(shape) = self.shapes.get_mut(&uid) {
+                    shape.entity.velocity = Vector3::from(velocity);
+                }
+            },
+            Command::SetSpin { uid, rate } => {
+                let uid = Uid::from(self.recorder.uid(uid));
+                if let Some(shape) = self.shapes.get_mut(&uid) {
+                    shape.set_rotation_rate(Vector3::from(rate));
+                }
+            },
+            Command::SetRotationScale { uid, rotation, scale } => {
+                let uid = Uid::from(self.recorder.uid(uid));
+                if let Some(shape) = self.shapes.get_mut(&uid) {
+                    let [x, y, z, w] = rotation;
+                    shape.entity.transform.rotation = UnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z));
+                    shape.entity.transform.scale = Vector3::from(scale);
+                }
+            },
+            Command::RemoveObject { uid } => self.remove_object(self.recorder.uid(uid)),
+            Command::SetObjectRenderer { uid, renderer } => self.set_object_renderer(self.recorder.uid(uid), renderer)?,
+            Command::SetParent { child, parent } => self.set_parent(self.recorder.uid(child), self.recorder.uid(parent))?,
+            Command::ClearParent { child } => self.clear_parent(self.recorder.uid(child))?,
+        }
+        Ok(())
+    }
+
+    fn set_light_attenuator(&mut self, index: usize, attenuator: Attenuator) -> Result<(), JsValue> {
+        let light = self.lights.get_mut(index)
+            .ok_or(CmcError::missing_val(format!("Light {}", index)))?;
+        light.set_attenuator(attenuator);
+        Ok(())
     }
 
     fn lookup_callback(&self, event: &str) -> Option<Rc<Closure<dyn FnMut(Event)>>> {
@@ -183,19 +1274,64 @@ pub fn cmc_init() {
     trace!("Info:\n Git version: {}", GIT_VERSION);
 }
 
+/// [{uri, loaded, total}] in bytes for every file fetched so far, total is undefined when unknown.
+/// Works before the client is made, while its models are still downloading.
+#[wasm_bindgen]
+pub fn asset_progress() -> Result<JsValue, JsValue> {
+    let files = Array::new();
+    for (uri, progress) in assets::progress::snapshot() {
+        let entry = Object::new();
+        Reflect::set(&entry, &"uri".into(), &uri.as_str().into())?;
+        Reflect::set(&entry, &"loaded".into(), &(progress.loaded as f64).into())?;
+        let total = progress.total.map_or(JsValue::UNDEFINED, |total| (total as f64).into());
+        Reflect::set(&entry, &"total".into(), &total)?;
+        files.push(&entry);
+    }
+    Ok(files.into())
+}
+
+/// [{file, line, message}] for every error reported since the last call, oldest first.
+/// Works before the client is made, so asset failures during construction show up too.
+#[wasm_bindgen]
+pub fn take_errors() -> Result<JsValue, JsValue> {
+    let errors = Array::new();
+    for error in error_report::take() {
+        let entry = Object::new();
+        Reflect::set(&entry, &"file".into(), &error.file.into())?;
+        Reflect::set(&entry, &"line".into(), &error.line.into())?;
+        Reflect::set(&entry, &"message".into(), &error.msg.as_str().into())?;
+        errors.push(&entry);
+    }
+    Ok(errors.into())
+}
+
 fn setup_canvas(document: &Rc<Document>) -> Result<HtmlCanvasElement, JsValue> {
     let canvas = document.get_element_by_id(RUST_CANVAS).ok_or(CmcError::missing_val(RUST_CANVAS))?;
     let canvas = canvas.dyn_into::<HtmlCanvasElement>()?;
     Ok(canvas)
 }
 
-fn setup_gl_context(canvas: &Rc<HtmlCanvasElement>, print_context_info: bool) -> Result<web_sys::WebGlRenderingContext, JsValue> {
-    let context: web_sys::WebGlRenderingContext = canvas
-        .get_context("webgl")?
-        .ok_or(JsValue::from_str("Failed to get webgl context"))?
-        .dyn_into()?;
+fn setup_gl_context(canvas: &Rc<HtmlCanvasElement>, options: &ContextOptions, print_context_info: bool) -> Result<web_sys::WebGlRenderingContext, JsValue> {
+    // Attributes only apply to the first getContext on a canvas, so nothing else may ask for one earlier
+    let webgl2 = match options.webgl2 {
+        true => canvas.get_context_with_context_options("webgl2", &options.attributes())?,
+        false => None,
+    };
+    let context: web_sys::WebGlRenderingContext = match webgl2 {
+        // WebGL2 has every WebGL1 call, and web-sys looks methods up on the object itself
+        Some(context) => context.unchecked_into(),
+        None => {
+            if options.webgl2 {
+                log::warn!("No WebGL2, falling back to WebGL1");
+            }
+            canvas.get_context_with_context_options("webgl", &options.attributes())?
+                .ok_or(JsValue::from_str("Failed to get webgl context"))?
+                .dyn_into()?
+        },
+    };
 
     if print_context_info {
+        debug!("WebGL2: {}", render::is_webgl2(&context));
         debug!("Max Vertex Attributes: {}", WebGL::MAX_VERTEX_ATTRIBS);
         debug!("Max Vertex Uniform vectors: {}", WebGL::MAX_VERTEX_UNIFORM_VECTORS);
         debug!("Max Fragment Uniform vectors: {}", WebGL::MAX_FRAGMENT_UNIFORM_VECTORS);
@@ -204,6 +1340,9 @@ fn setup_gl_context(canvas: &Rc<HtmlCanvasElement>, print_context_info: bool) ->
 
     context.enable(WebGL::DEPTH_TEST);
     context.enable(WebGL::BLEND);
+    // glTF winds front faces counter clockwise, CULL_FACE itself is switched per draw
+    context.cull_face(WebGL::BACK);
+    context.front_face(WebGL::CCW);
     context.blend_func(WebGL::SRC_ALPHA, WebGL::ONE_MINUS_SRC_ALPHA);
     context.clear_color(0.5, 0.5, 0.5, 1.);
     context.clear_depth(1.);
@@ -229,6 +1368,7 @@ where
 fn attach_pointerlock_handler(client: &mut CmcClient) -> Result<(), JsValue> {
     let mousemove_event = "mousemove";
     let scene_clone = client.scene.clone();
+    let settings_clone = client.input_settings.clone();
     let mousemove_handler = move |event: Event| {
         let event = event.dyn_into::<web_sys::MouseEvent>();
         if let Ok(event) = event {
@@ -236,7 +1376,7 @@ fn attach_pointerlock_handler(client: &mut CmcClient) -> Result<(), JsValue> {
             let y = -event.movement_y() as f32;
             {
                 let mut scene = scene_clone.write().unwrap();
-                scene.mouse_rotate([x, y, 0.]);
+                scene.mouse_rotate([x, y, 0.], &settings_clone.read().unwrap());
             }
         } else {
             log::warn!("Failed to convert event into mouseevent");
@@ -244,6 +1384,19 @@ fn attach_pointerlock_handler(client: &mut CmcClient) -> Result<(), JsValue> {
     };
     let mousemove_callback = client.add_callback(mousemove_event, Box::new(mousemove_handler))?;
 
+    let wheel_event = "wheel";
+    let scene_clone = client.scene.clone();
+    let settings_clone = client.input_settings.clone();
+    let wheel_handler = move |event: Event| {
+        if let Some(event) = event.dyn_ref::<web_sys::WheelEvent>() {
+            let zoom = settings_clone.read().unwrap().zoom;
+            scene_clone.write().unwrap().zoom(-event.delta_y() as f32 * zoom);
+        } else {
+            log::warn!("Failed to convert event into wheelevent");
+        }
+    };
+    let wheel_callback = client.add_callback(wheel_event, Box::new(wheel_handler))?;
+
     let document = client.document.clone();
     let keydown_event = "keydown";
     let key_state_clone = client.key_state.clone();
@@ -251,14 +1404,36 @@ fn attach_pointerlock_handler(client: &mut CmcClient) -> Result<(), JsValue> {
         let event = event.dyn_into::<web_sys::KeyboardEvent>();
         if let Ok(event) = event {
             log::info!("Keydown event: {}", event.code());
-            key_state_clone.write().unwrap().set_key(event.code());
+            key_state_clone.write().unwrap().set_key(event.code(), true);
         } else {
             log::warn!("Failed to convert event into keyboardevent");
         }
     };
     let keydown_callback = client.add_callback(keydown_event, Box::new(keydown_handler))?;
 
+    let keyup_event = "keyup";
+    let key_state_clone = client.key_state.clone();
+    let keyup_handler = move |event: Event| {
+        if let Some(event) = event.dyn_ref::<web_sys::KeyboardEvent>() {
+            key_state_clone.write().unwrap().set_key(event.code(), false);
+        } else {
+            log::warn!("Failed to convert event into keyboardevent");
+        }
+    };
+    let keyup_callback = client.add_callback(keyup_event, Box::new(keyup_handler))?;
+
+    // Keyups that happen while another window has focus never arrive, so let go of everything
+    let blur_event = "blur";
+    let key_state_clone = client.key_state.clone();
+    let blur_handler = move |_: Event| {
+        key_state_clone.write().unwrap().clear();
+    };
+    let blur_callback = client.add_callback(blur_event, Box::new(blur_handler))?;
+    let window = web_sys::window().ok_or(CmcError::missing_val("window"))?;
+    attach_handler(&window, blur_event, blur_callback)?;
+
     let document_clone = client.document.clone();
+    let key_state_clone = client.key_state.clone();
     let pointerlockchange_handler = move |_event: Event| {
         let element = document_clone.pointer_lock_element();
         log::debug!("pointerlockchange");
@@ -267,16 +1442,21 @@ fn attach_pointerlock_handler(client: &mut CmcClient) -> Result<(), JsValue> {
             vec![
                 attach_handler(document_clone.as_ref(), mousemove_event, mousemove_callback.clone()),
                 attach_handler(document_clone.as_ref(), keydown_event, keydown_callback.clone()),
+                attach_handler(document_clone.as_ref(), keyup_event, keyup_callback.clone()),
+                attach_handler(document_clone.as_ref(), wheel_event, wheel_callback.clone()),
             ]
         } else {
             log::debug!("Detaching mousemove handler");
+            key_state_clone.write().unwrap().clear();
             vec![
                 detach_handler(document_clone.as_ref(), mousemove_event, mousemove_callback.clone()),
                 detach_handler(document_clone.as_ref(), keydown_event, keydown_callback.clone()),
+                detach_handler(document_clone.as_ref(), keyup_event, keyup_callback.clone()),
+                detach_handler(document_clone.as_ref(), wheel_event, wheel_callback.clone()),
             ]
         };
         if let Err(e) = result.into_iter().collect::<Result<Vec<()>, JsValue>>() {
-            log::error!("Attach/Detach failed: {:?}", e);
+            report_error!("Attach/Detach failed: {:?}", e);
         }
     };
     let event = "pointerlockchange";
@@ -285,7 +1465,7 @@ fn attach_pointerlock_handler(client: &mut CmcClient) -> Result<(), JsValue> {
     attach_handler(document.as_ref(), "mozpointerlockchange", pointerlockchange_callback)?;
 
     let pointerlockerror_handler = move |_: Event| {
-        log::error!("Pointerlock error!");
+        report_error!("Pointerlock error!");
     };
     let pointerlockerror_event = "pointerlockerror";
     let pointerlockerror_callback = client.add_callback(pointerlockerror_event, Box::new(pointerlockerror_handler))?;
@@ -299,10 +1479,15 @@ fn attach_mouse_onclick_handler(client: &mut CmcClient) -> Result<(), JsValue> {
     let event = "click";
     let canvas_clone = client.canvas.clone();
     let document_clone = client.document.clone();
-    let handler = move |_event: Event| {
+    let spawn_points_clone = client.spawn_points.clone();
+    let handler = move |event: Event| {
         let element = document_clone.pointer_lock_element();
         if element.is_none() || element.unwrap().id().as_str() != RUST_CANVAS {
             canvas_clone.request_pointer_lock();
+        } else if event.dyn_ref::<web_sys::MouseEvent>().map_or(false, |e| e.shift_key()) {
+            // While locked the cursor is pinned to the middle of the canvas
+            let center = [canvas_clone.width() as f32 / 2., canvas_clone.height() as f32 / 2.];
+            spawn_points_clone.write().unwrap().push(center);
         };
     };
 
@@ -312,6 +1497,49 @@ fn attach_mouse_onclick_handler(client: &mut CmcClient) -> Result<(), JsValue> {
     Ok(())
 }
 
+/// Three finite numbers from JS, such as [x, y, z], as a vector. what names them in the error.
+fn vector3_from(values: &[f32], what: &str) -> CmcResult<Vector3<f32>> {
+    match values {
+        [x, y, z] if values.iter().all(|v| v.is_finite()) => Ok(Vector3::new(*x, *y, *z)),
+        _ => Err(CmcError::conversion_failed(format!("{} {:?}", what, values))),
+    }
+}
+
+fn rotation_scale_command(uid: Uid, transform: &Transform) -> Command {
+    let rotation = transform.rotation.coords;
+    Command::SetRotationScale {
+        uid: uid.into(),
+        rotation: [rotation.x, rotation.y, rotation.z, rotation.w],
+        scale: transform.scale.into(),
+    }
+}
+
+fn create_select<F>(document: &Document, label: &str, options: &[String], selected: &str, mut func: F) -> Result<(Element, HtmlSelectElement), JsValue>
+where
+    F: FnMut(String) + 'static,
+{
+    let html_label = document.create_element("p")?;
+    html_label.set_inner_html(label);
+    let html_select: HtmlSelectElement = document.create_element("select")?.dyn_into::<HtmlSelectElement>()?;
+    for option in options {
+        let html_option = document.create_element("option")?;
+        html_option.set_attribute("value", option)?;
+        html_option.set_text_content(Some(option));
+        html_select.append_child(&html_option)?;
+    }
+    html_select.set_value(selected);
+    let handler = move |event: web_sys::Event| {
+        if let Some(target) = event.target() {
+            if let Some(target_inner) = target.dyn_ref::<HtmlSelectElement>() {
+                func(target_inner.value());
+            }
+        }
+    };
+    let handler = Closure::wrap(Box::new(handler) as Box<dyn FnMut(_)>);
+    html_select.add_event_listener_with_callback("change", &Function::from(handler.into_js_value()))?;
+    Ok((html_label, html_select))
+}
+
 fn create_slider<F>(document: &Document, label: &str, range: std::ops::Range<f32>, start: f32, mut func: F) -> Result<(Element, HtmlInputElement), JsValue>
 where
     F: FnMut(f64) + 'static,
@@ -1,5 +1,21 @@
 use nalgebra::Vector3;
 
+/// Range in meters against constant, linear and quadratic terms, the classic Ogre3D table.
+pub const ATTENUATION_PRESETS: &[(f32, [f32; 3])] = &[
+    (7., [1.0, 0.7, 1.8]),
+    (13., [1.0, 0.35, 0.44]),
+    (20., [1.0, 0.22, 0.20]),
+    (32., [1.0, 0.14, 0.07]),
+    (50., [1.0, 0.09, 0.032]),
+    (65., [1.0, 0.07, 0.017]),
+    (100., [1.0, 0.045, 0.0075]),
+    (160., [1.0, 0.027, 0.0028]),
+    (200., [1.0, 0.022, 0.0019]),
+    (325., [1.0, 0.014, 0.0007]),
+    (600., [1.0, 0.007, 0.0002]),
+    (3250., [1.0, 0.0014, 0.000007]),
+];
+
 pub struct Attenuator {
     val: [f32; 3],
 }
@@ -11,7 +27,15 @@ impl Attenuator {
     }
 
     pub fn new_7m() -> Self {
-        Self::new(1.0, 0.7, 1.8)
+        Self::for_range(7.)
+    }
+
+    /// The shortest preset that still reaches range, or the longest one there is.
+    pub fn for_range(range: f32) -> Self {
+        let (_, val) = ATTENUATION_PRESETS.iter()
+            .find(|(preset, _)| *preset >= range)
+            .unwrap_or(&ATTENUATION_PRESETS[ATTENUATION_PRESETS.len() - 1]);
+        Self { val: *val }
     }
 
     pub fn as_slice(&self) -> &[f32] {
@@ -19,7 +43,29 @@ impl Attenuator {
     }
 }
 
+/// Sent to the shader as an int, in this order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    /// Shines every way from its location.
+    Point,
+    /// Shines from its location towards its target, inside the cone limits.
+    Spot,
+    /// Shines along its direction from infinitely far, with no falloff.
+    Directional,
+}
+
+impl LightKind {
+    pub fn as_index(&self) -> i32 {
+        match self {
+            LightKind::Point => 0,
+            LightKind::Spot => 1,
+            LightKind::Directional => 2,
+        }
+    }
+}
+
 pub struct Light {
+    pub kind: LightKind,
     pub color: Vector3<f32>,
     pub location: Vector3<f32>,
     pub direction: Vector3<f32>,
@@ -32,7 +78,17 @@ pub struct Light {
 
 impl Light {
     pub fn new_point(location: [f32; 3], color: [f32; 3], intensity: f32, attenuator: Attenuator) -> Self {
-        Self::new_spot(location, [0.; 3], color, 180.0, 180.0, intensity, attenuator)
+        let mut light = Self::new_spot(location, [0.; 3], color, 180.0, 180.0, intensity, attenuator);
+        light.kind = LightKind::Point;
+        light
+    }
+
+    /// Like sunlight, direction is the way the light travels.
+    pub fn new_directional(direction: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
+        let direction = Vector3::from(direction);
+        let mut light = Self::new_spot([0.; 3], direction.into(), color, 180.0, 180.0, intensity, Attenuator::new(1., 0., 0.));
+        light.kind = LightKind::Directional;
+        light
     }
 
     pub fn new_spot(location: [f32; 3], pointing_at: [f32; 3], color: [f32; 3], inner_limit: f32, outer_limit: f32, intensity: f32, attenuator: Attenuator) -> Self {
@@ -42,7 +98,21 @@ impl Light {
         let color = Vector3::from(color);
         let outer_limit = f32::cos(std::f32::consts::PI * outer_limit / 180.);
         let inner_limit = f32::cos(std::f32::consts::PI * inner_limit / 180.);
-        Light { location, color, direction, target, inner_limit, outer_limit, intensity, attenuator }
+        Light { kind: LightKind::Spot, location, color, direction, target, inner_limit, outer_limit, intensity, attenuator }
+    }
+
+    /// Roughly how much this light adds at point, the same falloff the shader uses.
+    pub fn influence_at(&self, point: &Vector3<f32>) -> f32 {
+        if self.kind == LightKind::Directional {
+            return self.intensity.max(1.);
+        }
+        let distance = (self.location - point).norm();
+        let att = self.attenuator.as_slice();
+        self.intensity.max(1.) / (att[0] + att[1] * distance + att[2] * distance * distance)
+    }
+
+    pub fn set_attenuator(&mut self, attenuator: Attenuator) {
+        self.attenuator = attenuator;
     }
 
     pub fn set_location(&mut self, location: [f32; 3]) {
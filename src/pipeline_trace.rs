@@ -0,0 +1,42 @@
+/// Stages an asset passes through on its way to the screen, in order.
+#[cfg(feature = "pipeline_trace")]
+#[derive(Clone, Copy, Debug)]
+pub enum PipelineStage {
+    Fetched,
+    Parsed,
+    BuffersResolved,
+    RendererBuilt,
+    TextureUploaded,
+    ObjectSpawned,
+    ObjectDrawn,
+}
+
+#[cfg(feature = "pipeline_trace")]
+impl PipelineStage {
+    pub fn id(&self) -> &'static str {
+        match self {
+            PipelineStage::Fetched => "1-fetched",
+            PipelineStage::Parsed => "2-parsed",
+            PipelineStage::BuffersResolved => "3-buffers",
+            PipelineStage::RendererBuilt => "4-renderer",
+            PipelineStage::TextureUploaded => "5-texture",
+            PipelineStage::ObjectSpawned => "6-spawned",
+            PipelineStage::ObjectDrawn => "7-drawn",
+        }
+    }
+}
+
+/// pipeline_trace!(Stage, asset, "format", args) logs under the "pipeline" target when the
+/// pipeline_trace feature is on, and expands to nothing otherwise. Statement position only.
+macro_rules! pipeline_trace {
+    ($stage:ident, $asset:expr) => {
+        pipeline_trace!($stage, $asset, "")
+    };
+    ($stage:ident, $asset:expr, $($arg:tt)+) => {
+        #[cfg(feature = "pipeline_trace")]
+        {
+            log::info!(target: "pipeline", "[{}] {}: {}",
+                $crate::pipeline_trace::PipelineStage::$stage.id(), $asset, format_args!($($arg)+));
+        }
+    };
+}
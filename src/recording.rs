@@ -0,0 +1,95 @@
+use crate::shape::ShapeDescriptor;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Every world mutation that can reach the client from the page.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum Command {
+    /// uid is the one handed out while recording, replay maps it to whatever it gets instead.
+    AddObject { uid: u32, renderer: String, location: [f32; 3] },
+    AddShape { uid: u32, shape: ShapeDescriptor, location: [f32; 3] },
+    SetVelocity { uid: u32, velocity: [f32; 3] },
+    /// Radians per second about each axis.
+    SetSpin { uid: u32, rate: [f32; 3] },
+    /// rotation is a quaternion in glTF order, [x, y, z, w].
+    SetRotationScale { uid: u32, rotation: [f32; 4], scale: [f32; 3] },
+    RemoveObject { uid: u32 },
+    SetObjectRenderer { uid: u32, renderer: String },
+    SetParent { child: u32, parent: u32 },
+    ClearParent { child: u32 },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordedCommand {
+    /// Frames since recording started.
+    pub frame: u64,
+    pub command: Command,
+}
+
+/// Logs commands against frame numbers and feeds a log back in at the same frames.
+pub struct Recorder {
+    frame: u64,
+    recording: Option<(u64, Vec<RecordedCommand>)>,
+    replaying: VecDeque<RecordedCommand>,
+    replay_start: u64,
+    uid_map: HashMap<u32, u32>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self {
+            frame: 0,
+            recording: None,
+            replaying: VecDeque::new(),
+            replay_start: 0,
+            uid_map: HashMap::new(),
+        }
+    }
+
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// initial describes the world as it is now, so a replay can rebuild it from nothing.
+    pub fn start(&mut self, initial: Vec<Command>) {
+        let commands = initial.into_iter().map(|command| RecordedCommand { frame: 0, command }).collect();
+        self.recording = Some((self.frame, commands));
+    }
+
+    pub fn stop(&mut self) -> Option<Vec<RecordedCommand>> {
+        self.recording.take().map(|(_, commands)| commands)
+    }
+
+    pub fn record(&mut self, command: Command) {
+        if let Some((start, commands)) = &mut self.recording {
+            commands.push(RecordedCommand { frame: self.frame - *start, command });
+        }
+    }
+
+    pub fn replay(&mut self, mut commands: Vec<RecordedCommand>) {
+        commands.sort_by_key(|c| c.frame);
+        self.replaying = commands.into();
+        self.replay_start = self.frame;
+        self.uid_map.clear();
+    }
+
+    /// Commands whose frame has come up, in the order they were recorded.
+    pub fn due(&mut self) -> Vec<Command> {
+        let elapsed = self.frame - self.replay_start;
+        let mut due = Vec::new();
+        while self.replaying.front().map_or(false, |c| c.frame <= elapsed) {
+            due.push(self.replaying.pop_front().unwrap().command);
+        }
+        due
+    }
+
+    pub fn map_uid(&mut self, recorded: u32, actual: u32) {
+        self.uid_map.insert(recorded, actual);
+    }
+
+    /// Recorded uid to the one this run handed out, unchanged when it wasn't spawned by the replay.
+    pub fn uid(&self, recorded: u32) -> u32 {
+        *self.uid_map.get(&recorded).unwrap_or(&recorded)
+    }
+}
@@ -0,0 +1,117 @@
+use crate::error::{CmcError, CmcResult};
+use super::common::check_gl_error;
+use image::{png::PngEncoder, ColorType};
+use web_sys::WebGlRenderingContext as WebGL;
+use web_sys::*;
+
+/// Largest supersampling factor a capture accepts.
+pub const MAX_CAPTURE_SCALE: u32 = 4;
+
+/// A one shot offscreen target at some multiple of the canvas size, drawn into once and read back.
+pub struct Capture {
+    framebuffer: WebGlFramebuffer,
+    color: WebGlTexture,
+    depth: WebGlRenderbuffer,
+    width: i32,
+    height: i32,
+    scale: u32,
+}
+
+impl Capture {
+    pub fn new(gl: &WebGlRenderingContext, width: i32, height: i32, scale: u32) -> CmcResult<Self> {
+        let scale = scale.clamp(1, MAX_CAPTURE_SCALE);
+        let (width, height) = (width * scale as i32, height * scale as i32);
+        let max_size = gl.get_parameter(WebGL::MAX_RENDERBUFFER_SIZE)?.as_f64().unwrap_or(0.) as i32;
+        if width > max_size || height > max_size {
+            return Err(CmcError::other(format!("Capture of {}x{}, the limit is {}", width, height, max_size)));
+        }
+        // wasm's usize is 32 bits, the RGBA readback of a big target doesn't fit in it
+        if (width as usize).checked_mul(height as usize).and_then(|pixels| pixels.checked_mul(4)).is_none() {
+            return Err(CmcError::other(format!("Capture of {}x{} is too big to read back", width, height)));
+        }
+
+        let framebuffer = gl.create_framebuffer()
+            .ok_or(CmcError::missing_val("Capture framebuffer"))?;
+        let color = gl.create_texture()
+            .ok_or(CmcError::missing_val("Capture color texture"))?;
+        let depth = gl.create_renderbuffer()
+            .ok_or(CmcError::missing_val("Capture depth renderbuffer"))?;
+        gl.bind_texture(WebGL::TEXTURE_2D, Some(&color));
+        gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_MIN_FILTER, WebGL::NEAREST as i32);
+        gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_MAG_FILTER, WebGL::NEAREST as i32);
+        gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_WRAP_S, WebGL::CLAMP_TO_EDGE as i32);
+        gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_WRAP_T, WebGL::CLAMP_TO_EDGE as i32);
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            WebGL::TEXTURE_2D, 0, WebGL::RGBA as i32, width, height, 0, WebGL::RGBA, WebGL::UNSIGNED_BYTE, None)?;
+        // Still bound, untextured draws would sample the target they're drawing into
+        gl.bind_texture(WebGL::TEXTURE_2D, None);
+        gl.bind_renderbuffer(WebGL::RENDERBUFFER, Some(&depth));
+        gl.renderbuffer_storage(WebGL::RENDERBUFFER, WebGL::DEPTH_COMPONENT16, width, height);
+
+        let capture = Self { framebuffer, color, depth, width, height, scale };
+        gl.bind_framebuffer(WebGL::FRAMEBUFFER, Some(&capture.framebuffer));
+        gl.framebuffer_texture_2d(WebGL::FRAMEBUFFER, WebGL::COLOR_ATTACHMENT0, WebGL::TEXTURE_2D, Some(&capture.color), 0);
+        gl.framebuffer_renderbuffer(WebGL::FRAMEBUFFER, WebGL::DEPTH_ATTACHMENT, WebGL::RENDERBUFFER, Some(&capture.depth));
+        if gl.check_framebuffer_status(WebGL::FRAMEBUFFER) != WebGL::FRAMEBUFFER_COMPLETE {
+            capture.delete(gl);
+            return Err(CmcError::other("Capture framebuffer is incomplete"));
+        }
+        gl.bind_framebuffer(WebGL::FRAMEBUFFER, None);
+        check_gl_error(gl, || "capture setup");
+        Ok(capture)
+    }
+
+    /// Points drawing at the capture target, clearing it.
+    pub fn begin(&self, gl: &WebGlRenderingContext) {
+        gl.bind_framebuffer(WebGL::FRAMEBUFFER, Some(&self.framebuffer));
+        gl.viewport(0, 0, self.width, self.height);
+        gl.clear(WebGL::COLOR_BUFFER_BIT | WebGL::DEPTH_BUFFER_BIT);
+    }
+
+    /// Reads the target back, box filters it down by the scale and encodes a PNG.
+    /// The canvas framebuffer and a viewport of canvas_size are restored, and the GL objects freed.
+    pub fn finish(self, gl: &WebGlRenderingContext, canvas_size: (i32, i32)) -> CmcResult<Vec<u8>> {
+        let mut pixels = vec![0u8; self.width as usize * self.height as usize * 4];
+        let read = gl.read_pixels_with_opt_u8_array(
+            0, 0, self.width, self.height, WebGL::RGBA, WebGL::UNSIGNED_BYTE, Some(pixels.as_mut_slice()));
+        gl.bind_framebuffer(WebGL::FRAMEBUFFER, None);
+        gl.viewport(0, 0, canvas_size.0, canvas_size.1);
+        self.delete(gl);
+        read?;
+
+        let scale = self.scale as usize;
+        let (width, height) = (self.width as usize / scale, self.height as usize / scale);
+        let samples = (scale * scale) as u32;
+        let mut image = vec![0u8; width * height * 4];
+        for y in 0..height {
+            // GL rows start at the bottom, PNG rows at the top
+            let out_row = height - 1 - y;
+            for x in 0..width {
+                let mut sum = [0u32; 4];
+                for sy in 0..scale {
+                    let row = (y * scale + sy) * self.width as usize;
+                    for sx in 0..scale {
+                        let at = (row + x * scale + sx) * 4;
+                        for c in 0..4 {
+                            sum[c] += pixels[at + c] as u32;
+                        }
+                    }
+                }
+                let at = (out_row * width + x) * 4;
+                for c in 0..4 {
+                    image[at + c] = (sum[c] / samples) as u8;
+                }
+            }
+        }
+
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png).encode(&image, width as u32, height as u32, ColorType::Rgba8)?;
+        Ok(png)
+    }
+
+    fn delete(&self, gl: &WebGlRenderingContext) {
+        gl.delete_framebuffer(Some(&self.framebuffer));
+        gl.delete_texture(Some(&self.color));
+        gl.delete_renderbuffer(Some(&self.depth));
+    }
+}
@@ -43,3 +43,50 @@ pub fn build_program(gl: &WebGlRenderingContext, vert_shader: &str, frag_shader:
     Ok(program)
 }
 
+/// A WebGL2 context passed around as WebGL1, see setup_gl_context.
+pub fn is_webgl2(gl: &WebGlRenderingContext) -> bool {
+    gl.get_parameter(WebGL::VERSION).ok()
+        .and_then(|version| version.as_string())
+        .map_or(false, |version| version.starts_with("WebGL 2"))
+}
+
+/// Logs any pending GL errors, tagged with where they were noticed. Compiled out of release builds,
+/// context is only called when there is an error so release builds don't build the string either.
+#[cfg(debug_assertions)]
+pub fn check_gl_error<C: std::fmt::Display, F: FnOnce() -> C>(gl: &WebGlRenderingContext, context: F) {
+    let mut errors = Vec::new();
+    loop {
+        let error = gl.get_error();
+        if error == WebGL::NO_ERROR {
+            break;
+        }
+        errors.push(error);
+        if error == WebGL::CONTEXT_LOST_WEBGL {
+            break;
+        }
+    }
+    if errors.is_empty() {
+        return;
+    }
+    let context = context();
+    for error in errors {
+        log::error!("GL error {} ({:#x}) after {}", gl_error_name(error), error, context);
+    }
+}
+
+#[cfg(not(debug_assertions))]
+#[inline(always)]
+pub fn check_gl_error<C: std::fmt::Display, F: FnOnce() -> C>(_gl: &WebGlRenderingContext, _context: F) {}
+
+#[cfg(debug_assertions)]
+fn gl_error_name(error: u32) -> &'static str {
+    match error {
+        WebGL::INVALID_ENUM => "INVALID_ENUM",
+        WebGL::INVALID_VALUE => "INVALID_VALUE",
+        WebGL::INVALID_OPERATION => "INVALID_OPERATION",
+        WebGL::INVALID_FRAMEBUFFER_OPERATION => "INVALID_FRAMEBUFFER_OPERATION",
+        WebGL::OUT_OF_MEMORY => "OUT_OF_MEMORY",
+        WebGL::CONTEXT_LOST_WEBGL => "CONTEXT_LOST_WEBGL",
+        _ => "UNKNOWN",
+    }
+}
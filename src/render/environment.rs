@@ -0,0 +1,45 @@
+use crate::error::{CmcError, CmcResult};
+use super::{common::check_gl_error, gob::GobImage};
+use web_sys::WebGlRenderingContext as WebGL;
+use web_sys::*;
+
+/// Texture unit the environment lives on, past the material slots.
+pub const ENVIRONMENT_UNIT: u32 = 5;
+
+/// A single equirectangular environment image, sampled for ambient light.
+pub struct Environment {
+    texture: WebGlTexture,
+}
+
+impl Environment {
+    pub fn new(gl: &WebGlRenderingContext, image: &GobImage) -> CmcResult<Self> {
+        let texture = gl.create_texture()
+            .ok_or(CmcError::missing_val("Environment texture creation"))?;
+        gl.active_texture(WebGL::TEXTURE0 + ENVIRONMENT_UNIT);
+        gl.bind_texture(WebGL::TEXTURE_2D, Some(&texture));
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            image.target, image.level, image.internal_format, image.width, image.height, image.border, image.format, image.data_type, Some(image.data.as_slice()))?;
+        let power_of_two = |v: i32| v > 0 && (v & (v - 1)) == 0;
+        if power_of_two(image.width) && power_of_two(image.height) {
+            // Blurry mip levels stand in for a prefiltered irradiance map
+            gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_WRAP_S, WebGL::REPEAT as i32);
+            gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_MIN_FILTER, WebGL::LINEAR_MIPMAP_LINEAR as i32);
+            gl.generate_mipmap(WebGL::TEXTURE_2D);
+        } else {
+            log::warn!("Environment is {}x{}, without power of two sides it can't be blurred", image.width, image.height);
+            gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_WRAP_S, WebGL::CLAMP_TO_EDGE as i32);
+            gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_MIN_FILTER, WebGL::LINEAR as i32);
+        }
+        gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_WRAP_T, WebGL::CLAMP_TO_EDGE as i32);
+        gl.active_texture(WebGL::TEXTURE0);
+        check_gl_error(gl, || "environment upload");
+        Ok(Self { texture })
+    }
+
+    /// Renderers sample it from ENVIRONMENT_UNIT, so binding once per frame covers them all.
+    pub fn bind(&self, gl: &WebGlRenderingContext) {
+        gl.active_texture(WebGL::TEXTURE0 + ENVIRONMENT_UNIT);
+        gl.bind_texture(WebGL::TEXTURE_2D, Some(&self.texture));
+        gl.active_texture(WebGL::TEXTURE0);
+    }
+}
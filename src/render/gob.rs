@@ -1,26 +1,110 @@
-use crate::{error::{CmcResult, CmcError}};
-use std::collections::HashMap;
-use gltf::{mesh::{Primitive, Semantic}, accessor::{Accessor, DataType}};
+use crate::{bounds::Aabb, error::{CmcResult, CmcError}};
+use std::collections::{HashMap, HashSet};
+use gltf::{mesh::{Primitive, Semantic}, accessor::{Accessor, DataType}, material::AlphaMode, texture::Texture};
 use web_sys::WebGlRenderingContext as GL;
 use image::DynamicImage;
+use nalgebra::Matrix3;
 
 #[derive(Debug)]
 pub struct Gob {
     pub accessors: HashMap<GobDataAttribute, GobDataAccess>,
     pub buffers: HashMap<usize, GobBuffer>,
-    pub images: HashMap<usize, GobImage>,
+    pub textures: Vec<GobTexture>,
+    pub bounds: Option<Aabb>,
+    pub uv_transform: Matrix3<f32>,
+    pub alpha_mode: GobAlphaMode,
+    /// Linear RGBA, multiplied with the base color texture when there is one.
+    pub base_color_factor: [f32; 4],
+    /// Both sides are meant to be seen, so it is never culled.
+    pub double_sided: bool,
+}
+
+/// How the material's alpha is read, the glTF default is Opaque.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GobAlphaMode {
+    /// Alpha is ignored and the surface is fully opaque.
+    Opaque,
+    /// Fragments below the cutoff (0.5 unless the material says otherwise) are dropped.
+    Mask(f32),
+    Blend,
+}
+
+/// Images can arrive after the geometry, in which case they start out Pending.
+#[derive(Clone, Debug)]
+pub enum TextureStatus {
+    Pending,
+    Available(GobImage),
+}
+
+/// The material maps a texture can fill, each one gets its own texture unit and sampler.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TextureSlot {
+    BaseColor = 0,
+    MetallicRoughness = 1,
+    Normal = 2,
+    Occlusion = 3,
+    Emissive = 4,
+}
+
+impl TextureSlot {
+    pub fn unit(&self) -> u32 {
+        *self as u32
+    }
+
+    pub fn sampler_name(&self) -> &'static str {
+        match self {
+            TextureSlot::BaseColor => "uBaseColorTexture",
+            TextureSlot::MetallicRoughness => "uMetallicRoughnessTexture",
+            TextureSlot::Normal => "uNormalTexture",
+            TextureSlot::Occlusion => "uOcclusionTexture",
+            TextureSlot::Emissive => "uEmissiveTexture",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GobTexture {
+    pub slot: TextureSlot,
+    pub image_index: usize,
+    pub status: TextureStatus,
+    pub sampler: GobSampler,
+}
+
+/// The glTF sampler as GL enums, filters left out by the asset are up to the renderer.
+#[derive(Clone, Copy, Debug)]
+pub struct GobSampler {
+    pub wrap_s: u32,
+    pub wrap_t: u32,
+    pub min_filter: Option<u32>,
+    pub mag_filter: Option<u32>,
+}
+
+impl GobSampler {
+    fn from_texture(texture: &Texture) -> Self {
+        let sampler = texture.sampler();
+        Self {
+            wrap_s: sampler.wrap_s().as_gl_enum(),
+            wrap_t: sampler.wrap_t().as_gl_enum(),
+            min_filter: sampler.min_filter().map(|f| f.as_gl_enum()),
+            mag_filter: sampler.mag_filter().map(|f| f.as_gl_enum()),
+        }
+    }
 }
 
 impl Gob {
-    pub fn new(primitive: &Primitive, avail_buffers: &Vec<GobBuffer>, avail_images: &Vec<GobImage>) -> CmcResult<Gob> {
+    pub fn new(primitive: &Primitive, avail_buffers: &Vec<GobBuffer>, avail_images: &HashMap<usize, GobImage>) -> CmcResult<Gob> {
         let mut accessors = HashMap::new();
         let mut gob_buffers = HashMap::new();
+        let mut bounds = None;
         for (sem, attr) in primitive.attributes() {
             let gob_attribute = GobDataAttribute::from(&sem);
             if let GobDataAttribute::Unhandled = gob_attribute {
                 log::warn!("Semantic: {:?} unhandled", sem);
                 continue;
             }
+            if let GobDataAttribute::Positions = gob_attribute {
+                bounds = accessor_bounds(&attr);
+            }
             let acc = GobDataAccess::from_accessor(&sem, &attr);
             let buffer_index = acc.buffer_index;
             if !gob_buffers.contains_key(&buffer_index) {
@@ -35,35 +119,134 @@ impl Gob {
         if let Some(index_acc) = primitive.indices() {
             let mut attr = GobDataAccess::new(GobDataAttribute::Indices, &index_acc);
             let offset = attr.offset as usize;
-            let size = index_acc.view().ok_or(CmcError::missing_val("No view for index accessor"))?.length();
+            // Index data is tightly packed, so only this accessor's elements are copied even from a shared view
+            let size = index_acc.count() * index_acc.size();
             if avail_buffers.len() <= attr.buffer_index {
                 log::error!("No matching buffer for indices");
                 Err(CmcError::missing_val("Missing buffer index"))?;
             }
             let copied_data = avail_buffers[attr.buffer_index].copy_from_buffer(offset, size)?;
             let new_gob_buffer = GobBuffer::new(copied_data, GobBufferTarget::ElementArray);
-            gob_buffers.insert(std::usize::MAX, new_gob_buffer);
-            attr.buffer_index = std::usize::MAX;
+            gob_buffers.insert(usize::MAX, new_gob_buffer);
+            attr.buffer_index = usize::MAX;
             attr.offset = 0;
             accessors.insert(GobDataAttribute::Indices, attr);
         }
 
         let material = primitive.material();
-        let mut gob_images = HashMap::new();
-        if let Some(texture_info) = material.pbr_metallic_roughness().base_color_texture() {
-            let image_index = texture_info.texture().index();
-            if image_index < avail_images.len() {
-                gob_images.insert(image_index, avail_images[image_index].clone());
-            }
+        let pbr = material.pbr_metallic_roughness();
+        let slots = [
+            (TextureSlot::BaseColor, pbr.base_color_texture().map(|t| t.texture())),
+            (TextureSlot::MetallicRoughness, pbr.metallic_roughness_texture().map(|t| t.texture())),
+            (TextureSlot::Normal, material.normal_texture().map(|t| t.texture())),
+            (TextureSlot::Occlusion, material.occlusion_texture().map(|t| t.texture())),
+            (TextureSlot::Emissive, material.emissive_texture().map(|t| t.texture())),
+        ];
+        let mut textures = Vec::new();
+        for (slot, texture) in slots.iter() {
+            let (image_index, sampler) = match texture {
+                Some(texture) => (texture.source().index(), GobSampler::from_texture(texture)),
+                None => continue,
+            };
+            let status = match avail_images.get(&image_index) {
+                Some(image) => TextureStatus::Available(image.clone()),
+                None => TextureStatus::Pending,
+            };
+            textures.push(GobTexture { slot: *slot, image_index, status, sampler });
         }
 
+        let alpha_mode = match material.alpha_mode() {
+            AlphaMode::Opaque => GobAlphaMode::Opaque,
+            AlphaMode::Mask => GobAlphaMode::Mask(material.alpha_cutoff()),
+            AlphaMode::Blend => GobAlphaMode::Blend,
+        };
+
         Ok(Gob {
             accessors,
             buffers: gob_buffers,
-            images: gob_images,
+            textures,
+            bounds,
+            uv_transform: Matrix3::identity(),
+            alpha_mode,
+            base_color_factor: pbr.base_color_factor(),
+            double_sided: material.double_sided(),
         })
     }
 
+    /// COLOR_0 as a vec4, its alpha fades the surface so it has to be drawn blended.
+    pub fn has_vertex_alpha(&self) -> bool {
+        self.accessors.get(&GobDataAttribute::Colors(0)).map_or(false, |acc| acc.num_items == 4)
+    }
+
+    /// Unique triangle edges as a LINES index buffer, byte indices are widened to shorts.
+    pub fn edge_indices(&self) -> Option<(GobBuffer, usize, u32)> {
+        let acc = self.accessors.get(&GobDataAttribute::Indices)?;
+        let lines: Vec<u32> = self.edges()?.iter().flatten().cloned().collect();
+        let (bytes, data_type) = match acc.data_type {
+            GL::UNSIGNED_INT => (lines.iter().flat_map(|i| i.to_le_bytes().to_vec()).collect(), GL::UNSIGNED_INT),
+            _ => (lines.iter().flat_map(|i| (*i as u16).to_le_bytes().to_vec()).collect(), GL::UNSIGNED_SHORT),
+        };
+        Some((GobBuffer::new(bytes, GobBufferTarget::ElementArray), lines.len(), data_type))
+    }
+
+    /// Each edge as two triangles for the thick line shader: position, other end, side.
+    pub fn thick_line_vertices(&self) -> Option<Vec<f32>> {
+        let positions = self.positions()?;
+        let mut vertices = Vec::new();
+        for [a, b] in self.edges()? {
+            let (a, b) = (positions.get(a as usize)?, positions.get(b as usize)?);
+            // Seen from b the edge runs the other way, so its sides swap
+            let corners = [(a, b, -1.), (a, b, 1.), (b, a, -1.), (a, b, -1.), (b, a, -1.), (b, a, 1.)];
+            for (this, other, side) in corners.iter() {
+                vertices.extend_from_slice(*this);
+                vertices.extend_from_slice(*other);
+                vertices.push(*side);
+            }
+        }
+        Some(vertices)
+    }
+
+    /// Float positions read back out of the vertex buffer.
+    fn positions(&self) -> Option<Vec<[f32; 3]>> {
+        let acc = self.accessors.get(&GobDataAttribute::Positions)?;
+        if acc.data_type != GL::FLOAT || acc.num_items != 3 {
+            return None;
+        }
+        let data = &self.buffers.get(&acc.buffer_index)?.data;
+        let stride = if acc.stride > 0 { acc.stride as usize } else { 12 };
+        let float = |at: usize| data.get(at..at + 4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        (0..acc.count)
+            .map(|i| {
+                let at = acc.offset as usize + i * stride;
+                Some([float(at)?, float(at + 4)?, float(at + 8)?])
+            })
+            .collect()
+    }
+
+    /// Unique triangle edges as vertex index pairs.
+    fn edges(&self) -> Option<Vec<[u32; 2]>> {
+        let acc = self.accessors.get(&GobDataAttribute::Indices)?;
+        let data = &self.buffers.get(&acc.buffer_index)?.data[acc.offset as usize..];
+        let read = |i: usize| -> Option<u32> {
+            match acc.data_type {
+                GL::UNSIGNED_BYTE => data.get(i).map(|b| *b as u32),
+                GL::UNSIGNED_SHORT => data.get(i * 2..i * 2 + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32),
+                GL::UNSIGNED_INT => data.get(i * 4..i * 4 + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                _ => None,
+            }
+        };
+        let mut seen = HashSet::new();
+        let mut edges = Vec::new();
+        for tri in 0..(acc.count / 3) {
+            let corners = [read(tri * 3)?, read(tri * 3 + 1)?, read(tri * 3 + 2)?];
+            for (a, b) in [(corners[0], corners[1]), (corners[1], corners[2]), (corners[2], corners[0])].iter() {
+                if seen.insert((*a.min(b), *a.max(b))) {
+                    edges.push([*a, *b]);
+                }
+            }
+        }
+        Some(edges)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -114,6 +297,23 @@ pub struct GobImage {
     pub data: Vec<u8>,
 }
 
+impl GobImage {
+    /// 1x1 white, stands in for an image that hasn't arrived so the texture is still complete.
+    pub fn placeholder() -> Self {
+        Self {
+            target: GL::TEXTURE_2D,
+            height: 1,
+            width: 1,
+            format: GL::RGBA,
+            border: 0,
+            internal_format: GL::RGBA as i32,
+            data_type: GL::UNSIGNED_BYTE,
+            data: vec![255; 4],
+            level: 0,
+        }
+    }
+}
+
 impl From<&DynamicImage> for GobImage {
     fn from(input: &DynamicImage) -> Self {
         let (width, height, data) = if let Some(image) = input.as_rgba8() {
@@ -142,6 +342,7 @@ pub enum GobDataAttribute {
     Positions,
     TexCoords(u32),
     Normals,
+    Colors(u32),
     Unhandled,
     Indices,
 }
@@ -150,10 +351,10 @@ impl From<&Semantic> for GobDataAttribute {
     fn from(semantic: &Semantic) -> Self {
         match semantic {
             Semantic::Positions => GobDataAttribute::Positions,
-            // Semantic::Extras(_name) => GobDataAttribute::Unhandled,
+            Semantic::Extras(_name) => GobDataAttribute::Unhandled,
             Semantic::Normals => GobDataAttribute::Normals,
             Semantic::Tangents => GobDataAttribute::Unhandled,
-            Semantic::Colors(_index) => GobDataAttribute::Unhandled,
+            Semantic::Colors(index) => GobDataAttribute::Colors(*index),
             Semantic::TexCoords(index) => GobDataAttribute::TexCoords(*index),
             Semantic::Joints(_index) => GobDataAttribute::Unhandled,
             Semantic::Weights(_index) => GobDataAttribute::Unhandled,
@@ -185,7 +386,8 @@ impl GobDataAccess {
         let buffer_index = buffer.index();
         let stride = view.stride().unwrap_or(0) as i32;
         let num_items = accessor.dimensions().multiplicity() as i32;
-        let offset = view.offset() as i32;
+        // Interleaved attributes share a view and are told apart by their own offset into it
+        let offset = (view.offset() + accessor.offset()) as i32;
         Self {
             attribute,
             buffer_index,
@@ -200,6 +402,15 @@ impl GobDataAccess {
     }
 }
 
+fn accessor_bounds(accessor: &Accessor) -> Option<Aabb> {
+    let to_vec = |value: gltf::json::Value| -> Option<Vec<f32>> {
+        value.as_array()?.iter().map(|v| v.as_f64().map(|v| v as f32)).collect()
+    };
+    let min = to_vec(accessor.min()?)?;
+    let max = to_vec(accessor.max()?)?;
+    Aabb::from_slices(&min, &max)
+}
+
 fn gltf_type_to_gl_type(input: DataType) -> u32 {
     use DataType::*;
     match input {
@@ -0,0 +1,95 @@
+use crate::{error::{CmcError, CmcResult}, scene::Scene};
+use super::common::{build_program, check_gl_error};
+use nalgebra::Matrix4;
+use web_sys::WebGlRenderingContext as WebGL;
+use web_sys::*;
+
+const VERT_SHADER: &str = r#"
+    attribute vec3 aPosition;
+    attribute vec3 aColor;
+
+    uniform mat4 uModelViewProjection;
+    varying vec3 vColor;
+
+    void main() {
+        gl_Position = uModelViewProjection * vec4(aPosition, 1.0);
+        vColor = aColor;
+    }
+"#;
+const FRAG_SHADER: &str = r#"
+    precision mediump float;
+    varying vec3 vColor;
+
+    void main() {
+        gl_FragColor = vec4(vColor, 1.0);
+    }
+"#;
+
+/// Unshaded colored line segments, interleaved as position then color.
+pub struct LineRenderer {
+    program: WebGlProgram,
+    buffer: WebGlBuffer,
+    count: i32,
+    a_position: u32,
+    a_color: u32,
+    u_mvp: WebGlUniformLocation,
+}
+
+impl LineRenderer {
+    pub fn new(gl: &WebGlRenderingContext, vertices: &[f32]) -> CmcResult<Self> {
+        let program = build_program(gl, VERT_SHADER, FRAG_SHADER)?;
+        let buffer = gl.create_buffer()
+            .ok_or(CmcError::missing_val("Failed to create line buffer"))?;
+        let bytes: Vec<u8> = vertices.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        gl.bind_buffer(WebGL::ARRAY_BUFFER, Some(&buffer));
+        gl.buffer_data_with_u8_array(WebGL::ARRAY_BUFFER, bytes.as_slice(), WebGL::STATIC_DRAW);
+        check_gl_error(gl, || "line buffer upload");
+        let a_position = gl.get_attrib_location(&program, "aPosition");
+        let a_color = gl.get_attrib_location(&program, "aColor");
+        if a_position < 0 || a_color < 0 {
+            Err(CmcError::missing_val("Line shader attributes"))?;
+        }
+        let u_mvp = gl.get_uniform_location(&program, "uModelViewProjection")
+            .ok_or(CmcError::missing_val("uModelViewProjection"))?;
+        Ok(Self {
+            program,
+            buffer,
+            count: (vertices.len() / 6) as i32,
+            a_position: a_position as u32,
+            a_color: a_color as u32,
+            u_mvp,
+        })
+    }
+
+    /// Unit length X red, Y green, Z blue from the origin.
+    pub fn new_axes(gl: &WebGlRenderingContext) -> CmcResult<Self> {
+        Self::new(gl, &[
+            0., 0., 0., 1., 0., 0.,  1., 0., 0., 1., 0., 0.,
+            0., 0., 0., 0., 1., 0.,  0., 1., 0., 0., 1., 0.,
+            0., 0., 0., 0., 0., 1.,  0., 0., 1., 0., 0., 1.,
+        ])
+    }
+
+    /// Drawn without depth testing so the lines show through the mesh they belong to.
+    pub fn render(&self, gl: &WebGlRenderingContext, scene: &Scene, model_mat: &Matrix4<f32>) {
+        gl.use_program(Some(&self.program));
+        gl.bind_buffer(WebGL::ARRAY_BUFFER, Some(&self.buffer));
+        let stride = 6 * std::mem::size_of::<f32>() as i32;
+        gl.vertex_attrib_pointer_with_i32(self.a_position, 3, WebGL::FLOAT, false, stride, 0);
+        gl.enable_vertex_attrib_array(self.a_position);
+        gl.vertex_attrib_pointer_with_i32(self.a_color, 3, WebGL::FLOAT, false, stride, 3 * std::mem::size_of::<f32>() as i32);
+        gl.enable_vertex_attrib_array(self.a_color);
+
+        let view = Matrix4::from_column_slice(&scene.get_view_as_vec());
+        let projection = Matrix4::from_column_slice(&scene.get_projection_as_vec());
+        let mvp = projection * view * model_mat;
+        gl.uniform_matrix4fv_with_f32_array(Some(&self.u_mvp), false, mvp.as_slice());
+
+        gl.disable(WebGL::DEPTH_TEST);
+        gl.draw_arrays(WebGL::LINES, 0, self.count);
+        gl.disable_vertex_attrib_array(self.a_position);
+        gl.disable_vertex_attrib_array(self.a_color);
+        gl.enable(WebGL::DEPTH_TEST);
+        check_gl_error(gl, || "line draw");
+    }
+}
@@ -1,21 +1,38 @@
-use crate::{assets::Model, error::{CmcResult, CmcError}};
-use gob::{Gob, GobBuffer, GobBufferTarget, GobImage};
+use crate::{assets::{Model, ObjModel}, error::{CmcResult, CmcError}};
+use gob::{Gob, GobBuffer, GobBufferTarget};
+pub use environment::Environment;
+pub use gob::GobImage;
 use std::{collections::HashMap, rc::Rc};
 use web_sys::*;
 use gltf::mesh::Mesh;
+use nalgebra::{Matrix3, Matrix4};
 
 mod shape;
+mod thick_line;
+mod capture;
 mod common;
+mod environment;
 mod gob;
+mod line;
+mod obj;
+mod post;
+mod primitive;
+mod settings;
 
+pub use capture::Capture;
+pub use common::is_webgl2;
+pub use line::LineRenderer;
+pub use post::{DepthOfField, PostProcess};
+pub use primitive::build_capsule;
+pub use settings::RenderSettings;
 pub use shape::ShapeRenderer;
 
 pub struct RenderCache {
     pub shape_renderers: HashMap<String, Rc<ShapeRenderer>>,
+    pub axes: LineRenderer,
 }
 
 impl RenderCache {
-    #[allow(unused)]
     pub fn add_shaperenderer<S: AsRef<str>>(&mut self, type_name: S, renderer: ShapeRenderer) {
         let renderer = Rc::new(renderer);
         if let Some(_) = self.shape_renderers.insert(type_name.as_ref().to_string(), renderer) {
@@ -23,45 +40,157 @@ impl RenderCache {
         }
     }
 
+    /// Uploads a late image to every renderer from the model that was waiting on it.
+    pub fn texture_arrived(&self, gl: &WebGlRenderingContext, model: &str, image_index: usize, image: &GobImage) -> CmcResult<()> {
+        for renderer in self.shape_renderers.values().filter(|r| r.model == model) {
+            renderer.upload_texture(gl, image_index, image)?;
+            pipeline_trace!(TextureUploaded, model, "image {} for {}", image_index, renderer.name);
+        }
+        Ok(())
+    }
+
     pub fn get_shaperenderer<S: AsRef<str>>(&self, type_name: S) -> Option<Rc<ShapeRenderer>> {
         self.shape_renderers.get(&type_name.as_ref().to_string()).map(|x| x.clone())
     }
 }
 
-pub fn build_rendercache(gl: &WebGlRenderingContext, models: &Vec<Model>) -> CmcResult<RenderCache> {
+pub fn build_rendercache(gl: &WebGlRenderingContext, models: &Vec<Model>, obj_models: &[ObjModel]) -> CmcResult<RenderCache> {
     let mut shape_renderers = HashMap::new();
     for model in models {
-        let (gltf, buffers, images) = (&model.gltf, &model.buffers, &model.images);
+        let (gltf, buffers) = (&model.gltf, &model.buffers);
         //log::trace!("Gltf loaded, {} buffers and {} images", buffers.len(), images.len());
-        for mesh in gltf.meshes() {
-            for (obj_name, renderer) in build_renderer_glb(gl, &mesh, buffers, images)? {
-                if let Some(old) = shape_renderers.insert(obj_name, Rc::new(renderer)) {
-                    log::warn!("Replaced renderer: {}", old.name);
-                }
+        for (node, transform) in scene_nodes(gltf) {
+            let mesh = match node.mesh() {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let extras = node_extras(&node, &mesh);
+            let clockwise = authored_clockwise(&transform, extras.as_ref());
+            let renderers = match build_renderer_glb(gl, &model.name, &mesh, transform, buffers, &model.texture_transforms) {
+                Ok(renderers) => renderers,
+                Err(e) => {
+                    log::warn!("{}: skipping mesh {}: {}", model.name, mesh.index(), e);
+                    continue;
+                },
+            };
+            for (obj_name, mut renderer) in renderers {
+                renderer.extras = extras.clone();
+                renderer.set_clockwise(clockwise);
+                insert_unique(&mut shape_renderers, &model.name, obj_name, renderer);
             }
         }
     }
+    for model in obj_models {
+        for (obj_name, renderer) in obj::build_renderers_obj(gl, model) {
+            insert_unique(&mut shape_renderers, &model.name, obj_name, renderer);
+        }
+    }
+    for (name, renderer) in primitive::build_primitives(gl)? {
+        if let Some(old) = shape_renderers.insert(name, Rc::new(renderer)) {
+            log::warn!("Replaced renderer: {}", old.name);
+        }
+    }
     Ok(RenderCache {
         shape_renderers,
+        axes: LineRenderer::new_axes(gl)?,
     })
 }
 
-fn build_renderer_glb(gl: &WebGlRenderingContext, object: &Mesh, buffers: &Vec<Vec<u8>>, images: &Vec<image::DynamicImage>) -> CmcResult<HashMap<String, ShapeRenderer>> {
-    let name = object.name().ok_or(CmcError::missing_val("Glb mesh name")).unwrap();
+/// A mesh used by several nodes, or two meshes sharing a name, keep every copy under a numbered name.
+fn insert_unique(shape_renderers: &mut HashMap<String, Rc<ShapeRenderer>>, model: &str, obj_name: String, mut renderer: ShapeRenderer) {
+    let mut unique_name = obj_name.clone();
+    let mut copy = 2;
+    while shape_renderers.contains_key(&unique_name) {
+        unique_name = format!("{}_{}", obj_name, copy);
+        copy += 1;
+    }
+    if unique_name != obj_name {
+        log::warn!("{}: renderer {} already exists, this one is {}", model, obj_name, unique_name);
+        renderer.name = unique_name.clone();
+    }
+    pipeline_trace!(RendererBuilt, model, "{}", unique_name);
+    shape_renderers.insert(unique_name, Rc::new(renderer));
+}
+
+fn build_renderer_glb(gl: &WebGlRenderingContext, model: &str, object: &Mesh, transform: Matrix4<f32>, buffers: &Vec<Vec<u8>>, texture_transforms: &HashMap<usize, Matrix3<f32>>) -> CmcResult<HashMap<String, ShapeRenderer>> {
+    let name = object.name().ok_or(CmcError::missing_val("Glb mesh name"))?;
     let name = format!("{}_{}", name, "glb");
-    let mut cache = HashMap::new();
+    let mut main: Option<ShapeRenderer> = None;
     let gob_buffers: Vec<GobBuffer> = buffers.iter().map(|b| GobBuffer::new(b.clone(), GobBufferTarget::Array)).collect();
-    let gob_images: Vec<GobImage> = images.iter().map(|i| GobImage::from(i)).collect();
+    // Images are streamed in after the renderers exist, see RenderCache::texture_arrived
+    let gob_images = HashMap::new();
     for prim in object.primitives() {
         let gob = Gob::new(&prim, &gob_buffers, &gob_images);
-        if let Ok(gob) = gob {
-            let renderer = ShapeRenderer::new(&name, gl, gob)?;
-            cache.insert(name.clone(), renderer);
+        if let Ok(mut gob) = gob {
+            if let Some(transform) = prim.material().index().and_then(|i| texture_transforms.get(&i)) {
+                gob.uv_transform = *transform;
+            }
+            let renderer = ShapeRenderer::new(&name, model, gl, gob, transform)?;
+            // Meshes split by material have several primitives, they all draw as one renderer
+            match main.as_mut() {
+                Some(main) => main.add_part(renderer),
+                None => main = Some(renderer),
+            }
         } else {
-            log::warn!("Gob build failed!");
+            log::warn!("{}: gob build failed for primitive {}", name, prim.index());
         }
     }
+    let mut cache = HashMap::new();
+    if let Some(main) = main {
+        cache.insert(name, main);
+    }
     Ok(cache)
 }
 
+/// Every node of the default scene (or the first, or failing that every root node) with its
+/// parents' transforms composed into its own.
+fn scene_nodes(gltf: &gltf::Document) -> Vec<(gltf::Node<'_>, Matrix4<f32>)> {
+    let roots: Vec<gltf::Node> = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+        Some(scene) => scene.nodes().collect(),
+        None => {
+            let children: Vec<usize> = gltf.nodes().flat_map(|n| n.children().map(|c| c.index())).collect();
+            gltf.nodes().filter(|n| !children.contains(&n.index())).collect()
+        },
+    };
+    let mut stack: Vec<(gltf::Node, Matrix4<f32>)> = roots.into_iter().map(|n| (n, Matrix4::identity())).collect();
+    let mut nodes = Vec::new();
+    while let Some((node, parent)) = stack.pop() {
+        let world = parent * Matrix4::from(node.transform().matrix());
+        stack.extend(node.children().map(|child| (child, world)));
+        nodes.push((node, world));
+    }
+    nodes
+}
 
+/// A mirroring node transform (negative determinant) flips the winding, per the glTF spec.
+/// An extras "frontFace" of "cw" or "ccw" overrides that for exporters that wind the other way.
+fn authored_clockwise(transform: &Matrix4<f32>, extras: Option<&serde_json::Value>) -> bool {
+    let mirrored = transform.fixed_slice::<nalgebra::U3, nalgebra::U3>(0, 0).determinant() < 0.;
+    match extras.and_then(|e| e.get("frontFace")).and_then(|f| f.as_str()) {
+        Some(face) if face.eq_ignore_ascii_case("cw") => true,
+        Some(face) if face.eq_ignore_ascii_case("ccw") => false,
+        Some(face) => {
+            log::warn!("Unknown frontFace {}, expected cw or ccw", face);
+            mirrored
+        },
+        None => mirrored,
+    }
+}
+
+/// Node and mesh extras merged into one object, the node's keys win. None when neither has any.
+fn node_extras(node: &gltf::Node, mesh: &Mesh) -> Option<serde_json::Value> {
+    let parse = |raw: &gltf::json::Extras| -> Option<serde_json::Value> {
+        let raw = raw.as_ref()?;
+        serde_json::from_str(raw.get())
+            .map_err(|e| log::warn!("Unreadable extras: {}", e))
+            .ok()
+    };
+    match (parse(node.extras()), parse(mesh.extras())) {
+        (Some(serde_json::Value::Object(node)), Some(serde_json::Value::Object(mut mesh))) => {
+            mesh.extend(node);
+            Some(serde_json::Value::Object(mesh))
+        },
+        (Some(node), _) => Some(node),
+        (None, mesh) => mesh,
+    }
+}
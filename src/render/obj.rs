@@ -0,0 +1,21 @@
+use crate::assets::{ObjModel, ObjObject};
+use super::{primitive::MeshData, shape::ShapeRenderer};
+use nalgebra::Matrix4;
+use web_sys::WebGlRenderingContext;
+
+/// One renderer per object in the file, named like glTF meshes but with an _obj suffix.
+pub fn build_renderers_obj(gl: &WebGlRenderingContext, model: &ObjModel) -> Vec<(String, ShapeRenderer)> {
+    let mut renderers = Vec::new();
+    for object in model.objects.iter() {
+        let name = format!("{}_{}", object.name, "obj");
+        match ShapeRenderer::new(&name, &model.name, gl, mesh_data(object).into_gob(), Matrix4::identity()) {
+            Ok(renderer) => renderers.push((name, renderer)),
+            Err(e) => log::warn!("{}: skipping object {}: {}", model.name, object.name, e),
+        }
+    }
+    renderers
+}
+
+fn mesh_data(object: &ObjObject) -> MeshData {
+    MeshData::new(object.positions.clone(), object.normals.clone(), object.tex_coords.clone(), object.indices.clone())
+}
@@ -0,0 +1,187 @@
+use crate::error::{CmcError, CmcResult};
+use super::common::{build_program, check_gl_error, is_webgl2};
+use std::cell::Cell;
+use web_sys::WebGlRenderingContext as WebGL;
+use web_sys::*;
+
+const VERT_SHADER: &str = r#"
+    attribute vec2 aPosition;
+    varying vec2 vUv;
+
+    void main() {
+        vUv = aPosition * 0.5 + 0.5;
+        gl_Position = vec4(aPosition, 0.0, 1.0);
+    }
+"#;
+const FRAG_SHADER: &str = r#"
+    precision mediump float;
+    varying vec2 vUv;
+
+    uniform sampler2D uColor;
+    uniform sampler2D uDepth;
+    uniform vec2 uTexelSize;
+    // near, far
+    uniform vec2 uClip;
+    uniform float uFocusDistance;
+    uniform float uAperture;
+
+    const float MAX_BLUR_PIXELS = 8.0;
+
+    float linear_depth(vec2 uv) {
+        float ndc = texture2D(uDepth, uv).r * 2.0 - 1.0;
+        return 2.0 * uClip.x * uClip.y / (uClip.y + uClip.x - ndc * (uClip.y - uClip.x));
+    }
+
+    void main() {
+        float depth = linear_depth(vUv);
+        float blur = clamp(uAperture * abs(depth - uFocusDistance) / max(depth, 0.001), 0.0, 1.0);
+        vec2 radius = uTexelSize * blur * MAX_BLUR_PIXELS;
+
+        vec4 sum = texture2D(uColor, vUv);
+        float count = 1.0;
+        // Two rings of eight taps, cheap enough for a showcase effect
+        for (int ring = 1; ring <= 2; ring++) {
+            for (int tap = 0; tap < 8; tap++) {
+                float angle = float(tap) * 0.785398 + float(ring) * 0.392699;
+                vec2 offset = vec2(cos(angle), sin(angle)) * radius * float(ring) / 2.0;
+                sum += texture2D(uColor, vUv + offset);
+                count += 1.0;
+            }
+        }
+        gl_FragColor = sum / count;
+    }
+"#;
+
+/// Depth of field settings, blur grows with distance from the focus plane.
+#[derive(Clone, Copy, Debug)]
+pub struct DepthOfField {
+    pub focus_distance: f32,
+    pub aperture: f32,
+}
+
+/// Renders the scene into color and depth textures, then blurs it onto the canvas.
+/// Needs WEBGL_depth_texture to read the depth back.
+pub struct PostProcess {
+    framebuffer: WebGlFramebuffer,
+    color: WebGlTexture,
+    depth: WebGlTexture,
+    size: Cell<(i32, i32)>,
+    program: WebGlProgram,
+    quad: WebGlBuffer,
+    a_position: u32,
+    u_color: WebGlUniformLocation,
+    u_depth: WebGlUniformLocation,
+    u_texel_size: WebGlUniformLocation,
+    u_clip: WebGlUniformLocation,
+    u_focus_distance: WebGlUniformLocation,
+    u_aperture: WebGlUniformLocation,
+}
+
+impl PostProcess {
+    pub fn new(gl: &WebGlRenderingContext) -> CmcResult<Self> {
+        // Depth textures are core in WebGL2, which doesn't list the extension
+        if !is_webgl2(gl) {
+            gl.get_extension("WEBGL_depth_texture")?
+                .ok_or(CmcError::missing_val("WEBGL_depth_texture extension"))?;
+        }
+        let program = build_program(gl, VERT_SHADER, FRAG_SHADER)?;
+        let framebuffer = gl.create_framebuffer()
+            .ok_or(CmcError::missing_val("Post process framebuffer"))?;
+        let color = gl.create_texture()
+            .ok_or(CmcError::missing_val("Post process color texture"))?;
+        let depth = gl.create_texture()
+            .ok_or(CmcError::missing_val("Post process depth texture"))?;
+        for texture in [&color, &depth].iter() {
+            gl.bind_texture(WebGL::TEXTURE_2D, Some(texture));
+            gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_MIN_FILTER, WebGL::NEAREST as i32);
+            gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_MAG_FILTER, WebGL::NEAREST as i32);
+            gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_WRAP_S, WebGL::CLAMP_TO_EDGE as i32);
+            gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_WRAP_T, WebGL::CLAMP_TO_EDGE as i32);
+        }
+
+        let quad = gl.create_buffer()
+            .ok_or(CmcError::missing_val("Post process quad"))?;
+        let corners: [f32; 12] = [-1., -1., 1., -1., 1., 1., -1., -1., 1., 1., -1., 1.];
+        let bytes: Vec<u8> = corners.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        gl.bind_buffer(WebGL::ARRAY_BUFFER, Some(&quad));
+        gl.buffer_data_with_u8_array(WebGL::ARRAY_BUFFER, bytes.as_slice(), WebGL::STATIC_DRAW);
+
+        let a_position = gl.get_attrib_location(&program, "aPosition");
+        if a_position < 0 {
+            Err(CmcError::missing_val("aPosition"))?;
+        }
+        let uniform = |name: &str| gl.get_uniform_location(&program, name).ok_or(CmcError::missing_val(name));
+        Ok(Self {
+            u_color: uniform("uColor")?,
+            u_depth: uniform("uDepth")?,
+            u_texel_size: uniform("uTexelSize")?,
+            u_clip: uniform("uClip")?,
+            u_focus_distance: uniform("uFocusDistance")?,
+            u_aperture: uniform("uAperture")?,
+            a_position: a_position as u32,
+            framebuffer,
+            color,
+            depth,
+            size: Cell::new((0, 0)),
+            program,
+            quad,
+        })
+    }
+
+    /// Redirects drawing into the offscreen textures, resizing them to the canvas first.
+    pub fn begin(&self, gl: &WebGlRenderingContext, width: i32, height: i32) -> CmcResult<()> {
+        if self.size.get() != (width, height) {
+            gl.bind_texture(WebGL::TEXTURE_2D, Some(&self.color));
+            gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                WebGL::TEXTURE_2D, 0, WebGL::RGBA as i32, width, height, 0, WebGL::RGBA, WebGL::UNSIGNED_BYTE, None)?;
+            gl.bind_texture(WebGL::TEXTURE_2D, Some(&self.depth));
+            gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                WebGL::TEXTURE_2D, 0, WebGL::DEPTH_COMPONENT as i32, width, height, 0, WebGL::DEPTH_COMPONENT, WebGL::UNSIGNED_INT, None)?;
+            gl.bind_texture(WebGL::TEXTURE_2D, None);
+            gl.bind_framebuffer(WebGL::FRAMEBUFFER, Some(&self.framebuffer));
+            gl.framebuffer_texture_2d(WebGL::FRAMEBUFFER, WebGL::COLOR_ATTACHMENT0, WebGL::TEXTURE_2D, Some(&self.color), 0);
+            gl.framebuffer_texture_2d(WebGL::FRAMEBUFFER, WebGL::DEPTH_ATTACHMENT, WebGL::TEXTURE_2D, Some(&self.depth), 0);
+            if gl.check_framebuffer_status(WebGL::FRAMEBUFFER) != WebGL::FRAMEBUFFER_COMPLETE {
+                gl.bind_framebuffer(WebGL::FRAMEBUFFER, None);
+                return Err(CmcError::missing_val("Complete post process framebuffer"));
+            }
+            check_gl_error(gl, || "post process resize");
+            self.size.set((width, height));
+        }
+        gl.bind_framebuffer(WebGL::FRAMEBUFFER, Some(&self.framebuffer));
+        gl.clear(WebGL::COLOR_BUFFER_BIT | WebGL::DEPTH_BUFFER_BIT);
+        Ok(())
+    }
+
+    /// Back to the canvas, drawing the blurred result over it. clip is (near, far).
+    pub fn finish(&self, gl: &WebGlRenderingContext, clip: (f32, f32), dof: &DepthOfField) {
+        gl.bind_framebuffer(WebGL::FRAMEBUFFER, None);
+        let (width, height) = self.size.get();
+        gl.use_program(Some(&self.program));
+        gl.active_texture(WebGL::TEXTURE0);
+        gl.bind_texture(WebGL::TEXTURE_2D, Some(&self.color));
+        gl.uniform1i(Some(&self.u_color), 0);
+        gl.active_texture(WebGL::TEXTURE1);
+        gl.bind_texture(WebGL::TEXTURE_2D, Some(&self.depth));
+        gl.uniform1i(Some(&self.u_depth), 1);
+        gl.active_texture(WebGL::TEXTURE0);
+        gl.uniform2f(Some(&self.u_texel_size), 1. / width as f32, 1. / height as f32);
+        gl.uniform2f(Some(&self.u_clip), clip.0, clip.1);
+        gl.uniform1f(Some(&self.u_focus_distance), dof.focus_distance);
+        gl.uniform1f(Some(&self.u_aperture), dof.aperture);
+
+        gl.bind_buffer(WebGL::ARRAY_BUFFER, Some(&self.quad));
+        gl.vertex_attrib_pointer_with_i32(self.a_position, 2, WebGL::FLOAT, false, 0, 0);
+        gl.enable_vertex_attrib_array(self.a_position);
+        gl.disable(WebGL::DEPTH_TEST);
+        gl.draw_arrays(WebGL::TRIANGLES, 0, 6);
+        gl.enable(WebGL::DEPTH_TEST);
+        gl.disable_vertex_attrib_array(self.a_position);
+        // Left bound, the next frame's untextured draws would sample the framebuffer they draw into
+        gl.active_texture(WebGL::TEXTURE1);
+        gl.bind_texture(WebGL::TEXTURE_2D, None);
+        gl.active_texture(WebGL::TEXTURE0);
+        gl.bind_texture(WebGL::TEXTURE_2D, None);
+        check_gl_error(gl, || "post process draw");
+    }
+}
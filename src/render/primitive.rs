@@ -0,0 +1,228 @@
+use crate::{bounds::Aabb, error::CmcResult};
+use super::{gob::{Gob, GobBuffer, GobBufferTarget, GobDataAccess, GobAlphaMode, GobDataAttribute}, shape::ShapeRenderer};
+use nalgebra::{Matrix3, Matrix4, Point3, Vector3};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use web_sys::WebGlRenderingContext as WebGL;
+
+/// Model name the built in meshes are registered under.
+pub const PRIMITIVE_MODEL: &str = "prim";
+const SEGMENTS: u16 = 32;
+const RINGS: u16 = 16;
+const INDEX_BUFFER: usize = usize::MAX;
+
+#[derive(Default)]
+pub(super) struct MeshData {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    tex_coords: Vec<[f32; 2]>,
+    indices: Vec<u16>,
+}
+
+impl MeshData {
+    /// Geometry built elsewhere, such as an obj file, with one normal and tex coord per position.
+    pub(super) fn new(positions: Vec<[f32; 3]>, normals: Vec<[f32; 3]>, tex_coords: Vec<[f32; 2]>, indices: Vec<u16>) -> Self {
+        Self { positions, normals, tex_coords, indices }
+    }
+
+    fn push(&mut self, position: [f32; 3], normal: [f32; 3], tex_coord: [f32; 2]) -> u16 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.tex_coords.push(tex_coord);
+        (self.positions.len() - 1) as u16
+    }
+
+    /// Flat quad from four corners given counter clockwise when seen from the normal side.
+    fn quad(&mut self, corners: [[f32; 3]; 4], normal: [f32; 3]) {
+        let uvs = [[0., 1.], [1., 1.], [1., 0.], [0., 0.]];
+        let base = self.positions.len() as u16;
+        for (corner, uv) in corners.iter().zip(uvs.iter()) {
+            self.push(*corner, normal, *uv);
+        }
+        self.indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+
+    /// Positions, normals and texture coordinates packed back to back in one array buffer.
+    pub(super) fn into_gob(self) -> Gob {
+        let count = self.positions.len();
+        let mut vertex_data = Vec::with_capacity(count * 8 * 4);
+        for value in self.positions.iter().flatten().chain(self.normals.iter().flatten()) {
+            vertex_data.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in self.tex_coords.iter().flatten() {
+            vertex_data.extend_from_slice(&value.to_le_bytes());
+        }
+        let index_data: Vec<u8> = self.indices.iter().flat_map(|i| i.to_le_bytes().to_vec()).collect();
+
+        let access = |attribute, num_items, offset, count, data_type, buffer_index| GobDataAccess {
+            attribute,
+            buffer_index,
+            data_type,
+            stride: 0,
+            count,
+            num_items,
+            normalized: false,
+            offset,
+            gl_attribute_index: None,
+        };
+        let mut accessors = HashMap::new();
+        accessors.insert(GobDataAttribute::Positions, access(GobDataAttribute::Positions, 3, 0, count, WebGL::FLOAT, 0));
+        accessors.insert(GobDataAttribute::Normals, access(GobDataAttribute::Normals, 3, (count * 12) as i32, count, WebGL::FLOAT, 0));
+        accessors.insert(GobDataAttribute::TexCoords(0), access(GobDataAttribute::TexCoords(0), 2, (count * 24) as i32, count, WebGL::FLOAT, 0));
+        accessors.insert(GobDataAttribute::Indices, access(GobDataAttribute::Indices, 1, 0, self.indices.len(), WebGL::UNSIGNED_SHORT, INDEX_BUFFER));
+
+        let mut buffers = HashMap::new();
+        buffers.insert(0, GobBuffer::new(vertex_data, GobBufferTarget::Array));
+        buffers.insert(INDEX_BUFFER, GobBuffer::new(index_data, GobBufferTarget::ElementArray));
+
+        let bounds = self.positions.iter()
+            .map(|p| Aabb::new(Point3::from(*p), Point3::from(*p)))
+            .fold(None, |acc: Option<Aabb>, b| Some(acc.map_or(b, |acc| acc.union(&b))));
+
+        Gob {
+            accessors,
+            buffers,
+            textures: Vec::new(),
+            bounds,
+            uv_transform: Matrix3::identity(),
+            alpha_mode: GobAlphaMode::Opaque,
+            base_color_factor: [1.; 4],
+            double_sided: false,
+        }
+    }
+}
+
+/// Unit cube centered on the origin.
+fn cube() -> MeshData {
+    let mut mesh = MeshData::default();
+    let faces: [Vector3<f32>; 6] = [Vector3::x(), -Vector3::x(), Vector3::y(), -Vector3::y(), Vector3::z(), -Vector3::z()];
+    for normal in faces.iter() {
+        // Two axes spanning the face, ordered so the winding faces outward
+        let up = if normal.y.abs() > 0.5 { Vector3::z() } else { Vector3::y() };
+        let right = up.cross(normal);
+        let corner = |r: f32, u: f32| -> [f32; 3] {
+            let p = (normal + right * r + up * u) * 0.5;
+            [p.x, p.y, p.z]
+        };
+        mesh.quad([corner(-1., -1.), corner(1., -1.), corner(1., 1.), corner(-1., 1.)], [normal.x, normal.y, normal.z]);
+    }
+    mesh
+}
+
+/// Sphere of diameter 1 centered on the origin.
+fn sphere() -> MeshData {
+    let mut mesh = MeshData::default();
+    for ring in 0..=RINGS {
+        let v = ring as f32 / RINGS as f32;
+        let polar = v * PI;
+        for segment in 0..=SEGMENTS {
+            let u = segment as f32 / SEGMENTS as f32;
+            let azimuth = u * 2. * PI;
+            let normal = [polar.sin() * azimuth.cos(), polar.cos(), -polar.sin() * azimuth.sin()];
+            mesh.push([normal[0] * 0.5, normal[1] * 0.5, normal[2] * 0.5], normal, [u, v]);
+        }
+    }
+    let row = SEGMENTS + 1;
+    for ring in 0..RINGS {
+        for segment in 0..SEGMENTS {
+            let a = ring * row + segment;
+            let b = a + row;
+            mesh.indices.extend_from_slice(&[a, b, a + 1, a + 1, b, b + 1]);
+        }
+    }
+    mesh
+}
+
+/// Cylinder of diameter 1 and height 1 along Y, centered on the origin.
+fn cylinder() -> MeshData {
+    let mut mesh = MeshData::default();
+    for segment in 0..=SEGMENTS {
+        let u = segment as f32 / SEGMENTS as f32;
+        let angle = u * 2. * PI;
+        let (x, z) = (angle.cos(), -angle.sin());
+        mesh.push([x * 0.5, 0.5, z * 0.5], [x, 0., z], [u, 0.]);
+        mesh.push([x * 0.5, -0.5, z * 0.5], [x, 0., z], [u, 1.]);
+    }
+    for segment in 0..SEGMENTS {
+        let top = segment * 2;
+        let bottom = top + 1;
+        mesh.indices.extend_from_slice(&[top, bottom, top + 2, top + 2, bottom, bottom + 2]);
+    }
+    for (y, normal_y) in [(0.5f32, 1f32), (-0.5, -1.)].iter() {
+        let center = mesh.push([0., *y, 0.], [0., *normal_y, 0.], [0.5, 0.5]);
+        let first = mesh.positions.len() as u16;
+        for segment in 0..=SEGMENTS {
+            let angle = segment as f32 / SEGMENTS as f32 * 2. * PI;
+            let (x, z) = (angle.cos(), -angle.sin());
+            mesh.push([x * 0.5, *y, z * 0.5], [0., *normal_y, 0.], [0.5 + x * 0.5, 0.5 + z * 0.5]);
+        }
+        for segment in 0..SEGMENTS {
+            let (a, b) = (first + segment, first + segment + 1);
+            if *normal_y > 0. {
+                mesh.indices.extend_from_slice(&[center, a, b]);
+            } else {
+                mesh.indices.extend_from_slice(&[center, b, a]);
+            }
+        }
+    }
+    mesh
+}
+
+/// Capsule along Y, a cylinder of the given half height capped with hemispheres of radius.
+fn capsule(half_height: f32, radius: f32) -> MeshData {
+    let mut mesh = MeshData::default();
+    let half_rings = RINGS / 2;
+    // The equator ring is doubled, once per hemisphere, and the strip between them is the side
+    for ring in 0..=RINGS + 1 {
+        let (polar_ring, offset) = if ring <= half_rings { (ring, half_height) } else { (ring - 1, -half_height) };
+        let polar = polar_ring as f32 / RINGS as f32 * PI;
+        let v = ring as f32 / (RINGS + 1) as f32;
+        for segment in 0..=SEGMENTS {
+            let u = segment as f32 / SEGMENTS as f32;
+            let azimuth = u * 2. * PI;
+            let normal = [polar.sin() * azimuth.cos(), polar.cos(), -polar.sin() * azimuth.sin()];
+            mesh.push([normal[0] * radius, normal[1] * radius + offset, normal[2] * radius], normal, [u, v]);
+        }
+    }
+    let row = SEGMENTS + 1;
+    for ring in 0..RINGS + 1 {
+        for segment in 0..SEGMENTS {
+            let a = ring * row + segment;
+            let b = a + row;
+            mesh.indices.extend_from_slice(&[a, b, a + 1, a + 1, b, b + 1]);
+        }
+    }
+    mesh
+}
+
+/// 1x1 square in the XZ plane facing +Y.
+fn plane() -> MeshData {
+    let mut mesh = MeshData::default();
+    mesh.quad([[-0.5, 0., 0.5], [0.5, 0., 0.5], [0.5, 0., -0.5], [-0.5, 0., -0.5]], [0., 1., 0.]);
+    mesh
+}
+
+/// Meshes generated in code so there is always something to spawn, even with no assets.
+pub fn build_primitives(gl: &WebGL) -> CmcResult<Vec<(String, ShapeRenderer)>> {
+    let meshes = vec![
+        ("prim_box", cube()),
+        ("prim_sphere", sphere()),
+        ("prim_cylinder", cylinder()),
+        ("prim_plane", plane()),
+    ];
+    let mut renderers = Vec::new();
+    for (name, mesh) in meshes {
+        let name = name.to_string();
+        let mut gob = mesh.into_gob();
+        // A lone quad vanishes from behind otherwise
+        gob.double_sided = name == "prim_plane";
+        let renderer = ShapeRenderer::new(&name, PRIMITIVE_MODEL, gl, gob, Matrix4::identity())?;
+        renderers.push((name, renderer));
+    }
+    Ok(renderers)
+}
+
+/// Capsules can't be stretched from one unit mesh without squashing the caps, so each proportion gets its own.
+pub fn build_capsule(gl: &WebGL, name: &str, half_height: f32, radius: f32) -> CmcResult<ShapeRenderer> {
+    ShapeRenderer::new(&name.to_string(), PRIMITIVE_MODEL, gl, capsule(half_height, radius).into_gob(), Matrix4::identity())
+}
@@ -0,0 +1,45 @@
+use super::post::DepthOfField;
+
+/// Per frame drawing options that aren't part of the camera.
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    /// Draw each mesh's edges on top of its shaded surface.
+    pub wireframe_overlay: bool,
+    pub wireframe_color: [f32; 3],
+    /// Edge width in pixels, anything over 1 is drawn as screen space quads.
+    pub wireframe_width: f32,
+    /// Draw each object's local X/Y/Z as red/green/blue lines.
+    pub show_axes: bool,
+    /// Darken fragments close to the y = 0 ground plane, a cheap stand-in for real shadows.
+    pub contact_shadow: bool,
+    /// How dark a fragment right on the ground gets, 0 to 1.
+    pub contact_shadow_strength: f32,
+    /// Height above the ground at which the darkening has faded out.
+    pub contact_shadow_height: f32,
+    /// Set once an environment image is bound, see Environment.
+    pub environment: bool,
+    /// Scale on the light taken from the environment image.
+    pub environment_intensity: f32,
+    /// Off by default, it costs an extra full screen pass.
+    pub depth_of_field: Option<DepthOfField>,
+    /// Skip back facing triangles, except on materials marked doubleSided.
+    pub cull_faces: bool,
+}
+
+impl RenderSettings {
+    pub fn new() -> Self {
+        Self {
+            wireframe_overlay: false,
+            wireframe_color: [0., 0., 0.],
+            wireframe_width: 1.,
+            show_axes: false,
+            contact_shadow: false,
+            contact_shadow_strength: 0.5,
+            contact_shadow_height: 1.,
+            environment: false,
+            environment_intensity: 1.,
+            depth_of_field: None,
+            cull_faces: true,
+        }
+    }
+}
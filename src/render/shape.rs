@@ -1,9 +1,8 @@
-use crate::{scene::Scene, error::{CmcError, CmcResult}, light::Light};
-use super::{common::build_program, gob::{Gob, GobDataAttribute}};
-use js_sys::WebAssembly;
-use nalgebra::{Isometry3, Vector3, Matrix4};
+use crate::{bounds::Aabb, scene::Scene, error::{CmcError, CmcResult}, light::Light};
+use super::{environment::ENVIRONMENT_UNIT, settings::RenderSettings, thick_line::ThickLines, common::{build_program, check_gl_error, is_webgl2}, gob::{Gob, GobAlphaMode, GobDataAttribute, GobImage, GobSampler, TextureSlot, TextureStatus}};
+use nalgebra::{Matrix3, Matrix4, Vector3};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use wasm_bindgen::JsCast;
 use web_sys::WebGlRenderingContext as WebGL;
 use web_sys::*;
 
@@ -11,19 +10,24 @@ const VERT_SHADER: &str = r#"
     attribute vec4 aPosition;
     attribute vec3 aNormal;
     attribute vec2 aTextureCoord0;
+    attribute vec4 aColor0;
 
     uniform mat4 uView;
     uniform mat4 uProjection;
     uniform mat4 uModel;
+    uniform mat3 uNormalMatrix;
+    uniform mat3 uTextureTransform;
     varying vec3 vNormal;
     varying vec3 vFragLoc;
     varying vec2 vTextureCoord0;
+    varying vec4 vColor0;
 
     void main() {
+        vColor0 = aColor0;
         gl_Position = uProjection * ((uView * uModel) * aPosition);
         vFragLoc = vec3(uModel * aPosition);
-        vNormal = mat3(uModel) * aNormal;
-        vTextureCoord0 = aTextureCoord0;
+        vNormal = uNormalMatrix * aNormal;
+        vTextureCoord0 = (uTextureTransform * vec3(aTextureCoord0, 1.0)).xy;
     }
 "#;
 const MAX_LIGHTS: usize = 10;
@@ -34,12 +38,36 @@ const FRAG_SHADER: &str = r#"
     varying vec3 vNormal;
     varying vec3 vFragLoc;
     varying vec2 vTextureCoord0;
+    // White when the mesh has no COLOR_0, vec3 colors come through with alpha 1
+    varying vec4 vColor0;
 
     uniform vec3 uAmbientLight;
     uniform vec3 uEyeLocation;
-    uniform sampler2D uTexture0;
-
+    uniform float uExposure;
+    uniform int uWireframe;
+    uniform vec3 uWireframeColor;
+    // 0 opaque, 1 mask against uAlphaCutoff, 2 blend
+    uniform int uAlphaMode;
+    uniform float uAlphaCutoff;
+    // strength, fade height; strength 0 turns it off
+    uniform vec2 uContactShadow;
+    // 0 none, 1 Reinhard, 2 ACES filmic fit
+    uniform int uToneMap;
+    // Only base color is shaded so far, the other maps are bound but unused
+    uniform sampler2D uBaseColorTexture;
+    uniform bool uHasBaseColorTexture;
+    uniform vec4 uBaseColorFactor;
+    uniform sampler2D uMetallicRoughnessTexture;
+    uniform sampler2D uNormalTexture;
+    uniform sampler2D uOcclusionTexture;
+    uniform sampler2D uEmissiveTexture;
+    // Equirectangular, its blurred mip levels approximate diffuse irradiance
+    uniform sampler2D uEnvironment;
+    uniform float uEnvironmentIntensity;
+
+    // kind 0 point, 1 spot, 2 directional
     struct Light {
+        int kind;
         vec3 color;
         vec3 location;
         vec3 direction;
@@ -50,26 +78,66 @@ const FRAG_SHADER: &str = r#"
 
         vec3 attenuator;
     };
-    uniform Light spot_lights[MAX_LIGHTS];
+    uniform Light lights[MAX_LIGHTS];
+    uniform int uNumLights;
+
+    // Base color textures are authored in sRGB, lighting has to happen in linear space
+    vec4 srgb_to_linear(vec4 color) {
+        return vec4(pow(color.rgb, vec3(2.2)), color.a);
+    }
+
+    vec3 linear_to_srgb(vec3 color) {
+        return pow(color, vec3(1.0 / 2.2));
+    }
+
+    vec2 equirect_uv(vec3 dir) {
+        return vec2(atan(dir.z, dir.x) / 6.28318530718 + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / 3.14159265359);
+    }
+
+    vec3 tone_map(vec3 color) {
+        if (uToneMap == 1) {
+            return color / (vec3(1.0) + color);
+        } else if (uToneMap == 2) {
+            return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+        }
+        return color;
+    }
 
     void main() {
+        if (uWireframe == 1) {
+            gl_FragColor = vec4(uWireframeColor, 1.0);
+            return;
+        }
         vec3 normal = normalize(vNormal);
         vec3 fragment_to_view = normalize(uEyeLocation - vFragLoc);
 
         vec3 lighting = uAmbientLight;
+        if (uEnvironmentIntensity > 0.0) {
+            vec3 irradiance = srgb_to_linear(texture2D(uEnvironment, equirect_uv(normal), 8.0)).rgb;
+            lighting += irradiance * uEnvironmentIntensity;
+        }
 
+        // GLSL ES 1.0 loops need a constant bound, stop early at the lights in use
         for(int j = 0; j < MAX_LIGHTS; j++) {
-            vec3 light_location = spot_lights[j].location;
-            vec3 light_direction = spot_lights[j].direction;
-            vec3 light_color = spot_lights[j].color;
-            float outer_limit = spot_lights[j].outer_limit;
-            float inner_limit = spot_lights[j].inner_limit;
-            vec3 attenuator = spot_lights[j].attenuator;
-            float intensity = spot_lights[j].intensity;
-
-            vec3 fragment_to_light = normalize(light_location - vFragLoc);
-            float dot_f2l_ldir = dot(fragment_to_light, normalize(-light_direction));
-            float inLight = smoothstep(outer_limit, inner_limit, dot_f2l_ldir);
+            if (j >= uNumLights) {
+                break;
+            }
+            vec3 light_location = lights[j].location;
+            vec3 light_direction = lights[j].direction;
+            vec3 light_color = lights[j].color;
+            float outer_limit = lights[j].outer_limit;
+            float inner_limit = lights[j].inner_limit;
+            vec3 attenuator = lights[j].attenuator;
+            float intensity = lights[j].intensity;
+
+            int kind = lights[j].kind;
+
+            vec3 fragment_to_light = kind == 2 ? normalize(-light_direction) : normalize(light_location - vFragLoc);
+            float inLight = 1.0;
+            if (kind == 1) {
+                float dot_f2l_ldir = dot(fragment_to_light, normalize(-light_direction));
+                inLight = smoothstep(outer_limit, inner_limit, dot_f2l_ldir);
+            }
             float diffuse_directional = inLight * max(dot(normal, fragment_to_light), 0.0);
             float specular = 0.0;
             if (diffuse_directional > 0.0) {
@@ -77,17 +145,39 @@ const FRAG_SHADER: &str = r#"
                 float viewable_reflection = dot(normal, half_vector);
                 specular = pow(max(viewable_reflection, 0.0), 32.0);
             }
-            float distance    = length(light_location - vFragLoc);
-            float attenuation = max(1.0, intensity) / (1.0 + attenuator.y * distance +
-    		    attenuator.z * (distance * distance));
-            lighting += (diffuse_directional + specular) * spot_lights[j].color * attenuation;
+            float attenuation = max(1.0, intensity);
+            if (kind != 2) {
+                float distance = length(light_location - vFragLoc);
+                attenuation /= 1.0 + attenuator.y * distance + attenuator.z * (distance * distance);
+            }
+            lighting += (diffuse_directional + specular) * lights[j].color * attenuation;
+        }
+
+        if (uContactShadow.x > 0.0) {
+            float above_ground = max(vFragLoc.y, 0.0);
+            lighting *= 1.0 - uContactShadow.x * (1.0 - smoothstep(0.0, uContactShadow.y, above_ground));
         }
 
-        gl_FragColor = texture2D(uTexture0, vTextureCoord0) * vec4(lighting, 1.0);
+        vec4 base_color = uBaseColorFactor;
+        if (uHasBaseColorTexture) {
+            base_color *= srgb_to_linear(texture2D(uBaseColorTexture, vTextureCoord0));
+        }
+        vec4 color = base_color * vColor0 * vec4(lighting, 1.0);
+        float alpha = color.a;
+        if (uAlphaMode == 0) {
+            alpha = 1.0;
+        } else if (uAlphaMode == 1) {
+            if (alpha < uAlphaCutoff) {
+                discard;
+            }
+            alpha = 1.0;
+        }
+        gl_FragColor = vec4(linear_to_srgb(tone_map(color.rgb * uExposure)), alpha);
     }
 "#;
 
 struct RenderLight {
+    kind: WebGlUniformLocation,
     color: WebGlUniformLocation,
     location: WebGlUniformLocation,
     direction: WebGlUniformLocation,
@@ -99,6 +189,7 @@ struct RenderLight {
 
 impl RenderLight {
     fn new_at_index(gl: &WebGlRenderingContext, program: &WebGlProgram, array_name: &str, index: usize) -> CmcResult<Self> {
+        let kind_name = format!("{}[{}].kind", array_name, index);
         let color_name = format!("{}[{}].color", array_name, index);
         let location_name = format!("{}[{}].location", array_name, index);
         let direction_name = format!("{}[{}].direction", array_name, index);
@@ -106,6 +197,8 @@ impl RenderLight {
         let outer_limit_name = format!("{}[{}].outer_limit", array_name, index);
         let intensity_name = format!("{}[{}].intensity", array_name, index);
         let attenuator_name = format!("{}[{}].attenuator", array_name, index);
+        let kind = gl.get_uniform_location(program, kind_name.as_str())
+            .ok_or(CmcError::missing_val(kind_name))?;
         let color = gl.get_uniform_location(program, color_name.as_str())
             .ok_or(CmcError::missing_val(color_name))?;
         let location = gl.get_uniform_location(program, location_name.as_str())
@@ -120,7 +213,7 @@ impl RenderLight {
             .ok_or(CmcError::missing_val(intensity_name))?;
         let attenuator = gl.get_uniform_location(program, attenuator_name.as_str())
             .ok_or(CmcError::missing_val(attenuator_name))?;
-        Ok(Self { color, location, inner_limit, outer_limit, direction, intensity, attenuator})
+        Ok(Self { kind, color, location, inner_limit, outer_limit, direction, intensity, attenuator})
     }
 
     fn populate_with(&self, gl: &WebGlRenderingContext, source_light: &Light) {
@@ -131,6 +224,7 @@ impl RenderLight {
         let outer_limit_location = &self.outer_limit;
         let intensity_location = &self.intensity;
         let attenuator_location = &self.attenuator;
+        gl.uniform1i(Some(&self.kind), source_light.kind.as_index());
         gl.uniform3fv_with_f32_array(Some(color_location), source_light.color.as_slice());
         gl.uniform3fv_with_f32_array(Some(location_location), source_light.location.as_slice());
         gl.uniform3fv_with_f32_array(Some(direction_location), source_light.direction.as_slice());
@@ -139,20 +233,30 @@ impl RenderLight {
         gl.uniform1f(Some(intensity_location), source_light.intensity);
         gl.uniform3fv_with_f32_array(Some(attenuator_location), source_light.attenuator.as_slice());
     }
+
+    /// Black contributes nothing, so an unused slot can't keep last frame's light.
+    fn clear(&self, gl: &WebGlRenderingContext) {
+        gl.uniform3fv_with_f32_array(Some(&self.color), &[0., 0., 0.]);
+    }
 }
 
 pub struct RenderScene {
     u_model: WebGlUniformLocation,
+    u_normal_matrix: WebGlUniformLocation,
     u_view: WebGlUniformLocation,
     u_projection: WebGlUniformLocation,
     u_ambient_light: WebGlUniformLocation,
     u_eye: WebGlUniformLocation,
+    u_exposure: WebGlUniformLocation,
+    u_tone_map: WebGlUniformLocation,
 }
 
 impl RenderScene {
     fn new(gl: &WebGlRenderingContext, program: &WebGlProgram) -> CmcResult<Self> {
         let u_model = gl.get_uniform_location(&program, "uModel")
             .ok_or(CmcError::missing_val("uModel"))?;
+        let u_normal_matrix = gl.get_uniform_location(&program, "uNormalMatrix")
+            .ok_or(CmcError::missing_val("uNormalMatrix"))?;
         let u_view = gl.get_uniform_location(&program, "uView")
             .ok_or(CmcError::missing_val("uView"))?;
         let u_projection = gl.get_uniform_location(&program, "uProjection")
@@ -162,108 +266,279 @@ impl RenderScene {
             .ok_or(CmcError::missing_val("uEyeLocation"))?;
         let u_ambient_light = gl.get_uniform_location(&program, "uAmbientLight")
             .ok_or(CmcError::missing_val("uAmbientLight"))?;
+        let u_exposure = gl.get_uniform_location(&program, "uExposure")
+            .ok_or(CmcError::missing_val("uExposure"))?;
+        let u_tone_map = gl.get_uniform_location(&program, "uToneMap")
+            .ok_or(CmcError::missing_val("uToneMap"))?;
         Ok(Self {
             u_model,
+            u_normal_matrix,
             u_view,
             u_eye,
             u_projection,
             u_ambient_light,
+            u_exposure,
+            u_tone_map,
         })
     }
 
     fn populate_with(&self, gl: &WebGlRenderingContext, external_scene: &Scene, model_mat: &Matrix4<f32>) {
         gl.uniform_matrix4fv_with_f32_array(Some(&self.u_model), false, model_mat.as_slice());
+        // Non-uniform scale skews normals, so they need the inverse transpose rather than mat3(uModel)
+        let normal_mat: Matrix3<f32> = model_mat.fixed_slice::<nalgebra::U3, nalgebra::U3>(0, 0).into();
+        let normal_mat = normal_mat.try_inverse().unwrap_or_else(Matrix3::identity).transpose();
+        gl.uniform_matrix3fv_with_f32_array(Some(&self.u_normal_matrix), false, normal_mat.as_slice());
         gl.uniform_matrix4fv_with_f32_array(Some(&self.u_view), false, external_scene.get_view_as_vec().as_slice());
         gl.uniform_matrix4fv_with_f32_array(Some(&self.u_projection), false, external_scene.get_projection_as_vec().as_slice());
         gl.uniform3fv_with_f32_array(Some(&self.u_eye), external_scene.get_eye_as_vec().as_slice());
 
-        let ambient_light = vec![0.1, 0.1, 0.1];
-        gl.uniform3fv_with_f32_array(Some(&self.u_ambient_light), ambient_light.as_slice());
+        gl.uniform3fv_with_f32_array(Some(&self.u_ambient_light), &external_scene.ambient());
+        gl.uniform1f(Some(&self.u_exposure), external_scene.exposure());
+        gl.uniform1i(Some(&self.u_tone_map), external_scene.tone_map() as i32);
     }
 }
 
+struct RenderLines {
+    buffer: WebGlBuffer,
+    count: i32,
+    data_type: u32,
+}
+
+struct RenderTexture {
+    slot: TextureSlot,
+    image_index: usize,
+    texture: WebGlTexture,
+    /// None when the shader doesn't read this slot and the compiler dropped the sampler.
+    u_texture: Option<WebGlUniformLocation>,
+    target: u32,
+    sampler: GobSampler,
+    available: Cell<bool>,
+}
+
 pub struct ShapeRenderer {
     pub name: String,
+    pub model: String,
+    /// glTF extras from the node and mesh this was built from.
+    pub extras: Option<serde_json::Value>,
+    /// The glTF node's own transform, applied under the object's.
+    base_transform: Matrix4<f32>,
+    /// WebGL::CCW unless the asset was authored the other way round.
+    front_face: Cell<u32>,
+    /// Lights left out last frame for going over MAX_LIGHTS, so the warning only repeats on a change.
+    lights_dropped: Cell<usize>,
     program: WebGlProgram,
     gob: Gob,
     geometry_buffers: HashMap<usize, WebGlBuffer>,
     scene: RenderScene,
     lights: Vec<RenderLight>,
-    textures: Vec<(WebGlTexture, WebGlUniformLocation, u32)>,
+    textures: Vec<RenderTexture>,
+    lines: Option<RenderLines>,
+    /// The mesh's other primitives, usually one per material, drawn along with this one.
+    parts: Vec<ShapeRenderer>,
+    /// Only built the first time a wide wireframe is asked for.
+    thick_lines: RefCell<Option<ThickLines>>,
+    u_wireframe: WebGlUniformLocation,
+    u_wireframe_color: WebGlUniformLocation,
+    u_texture_transform: WebGlUniformLocation,
+    u_contact_shadow: WebGlUniformLocation,
+    /// Only used to fall back to white when the mesh has no COLOR_0.
+    a_color0: Option<u32>,
+    u_environment: WebGlUniformLocation,
+    u_environment_intensity: WebGlUniformLocation,
+    u_alpha_mode: WebGlUniformLocation,
+    u_alpha_cutoff: WebGlUniformLocation,
+    u_num_lights: WebGlUniformLocation,
+    u_has_base_color_texture: WebGlUniformLocation,
+    u_base_color_factor: WebGlUniformLocation,
 }
 
-fn attr_location(attr_data: &GobDataAttribute) -> Option<u32> {
+fn attr_name(attr_data: &GobDataAttribute) -> Option<&'static str> {
     match attr_data {
-        GobDataAttribute::Positions => Some(0),
-        GobDataAttribute::TexCoords(0) => Some(2),
-        GobDataAttribute::Normals => Some(1),
+        GobDataAttribute::Positions => Some("aPosition"),
+        GobDataAttribute::TexCoords(0) => Some("aTextureCoord0"),
+        GobDataAttribute::Normals => Some("aNormal"),
+        GobDataAttribute::Colors(0) => Some("aColor0"),
         _ => None,
     }
 }
 
+/// None when the shader has no such attribute or the compiler dropped it.
+fn attr_location(gl: &WebGlRenderingContext, program: &WebGlProgram, attr_data: &GobDataAttribute) -> Option<u32> {
+    let location = gl.get_attrib_location(program, attr_name(attr_data)?);
+    if location < 0 { None } else { Some(location as u32) }
+}
+
 impl ShapeRenderer {
-    pub fn new(name: &String, gl: &WebGlRenderingContext, mut gob: Gob) -> CmcResult<Self> {
+    pub fn new(name: &String, model: &str, gl: &WebGlRenderingContext, mut gob: Gob, base_transform: Matrix4<f32>) -> CmcResult<Self> {
         let program = build_program(gl, VERT_SHADER, FRAG_SHADER)?;
         let mut geometry_buffers = HashMap::new();
-        let js_memory = wasm_bindgen::memory().dyn_into::<WebAssembly::Memory>()?.buffer();
-        let js_memory = js_sys::Uint8Array::new(&js_memory);
         for (index, gob_buffer) in gob.buffers.iter() {
-            let gb_slice = gob_buffer.data.as_slice();
-            let gb_location = gb_slice.as_ptr() as u32;
-            let gb_len = (gb_slice.len() * std::mem::size_of::<u8>()) as u32;
-            let js_buf = js_memory.subarray(gb_location, gb_location + gb_len);
             let gl_buf = gl.create_buffer()
                 .ok_or(CmcError::missing_val(format!("Failed to create buffer index: {}", index)))?;
             gl.bind_buffer(gob_buffer.target.to_gl(), Some(&gl_buf));
-            gl.buffer_data_with_array_buffer_view(gob_buffer.target.to_gl(), &js_buf, WebGL::STATIC_DRAW);
+            // Copied from the slice during the call, a view into wasm memory goes stale if the heap grows
+            gl.buffer_data_with_u8_array(gob_buffer.target.to_gl(), gob_buffer.data.as_slice(), WebGL::STATIC_DRAW);
+            check_gl_error(gl, || format!("{}: buffer {} upload", name, index));
             geometry_buffers.insert(*index, gl_buf);
         }
 
         for (attr, gob_data_access) in gob.accessors.iter_mut() {
-            gob_data_access.gl_attribute_index = attr_location(&attr);
+            gob_data_access.gl_attribute_index = attr_location(gl, &program, &attr);
         }
 
         let mut textures = Vec::new();
-        for (index, image) in gob.images.iter() {
-            let texture_name = format!("uTexture{}", index);
-            let u_texture = gl.get_uniform_location(&program, texture_name.as_str())
-                .ok_or(CmcError::missing_val(texture_name))?;
+        for gob_texture in gob.textures.iter() {
+            let u_texture = gl.get_uniform_location(&program, gob_texture.slot.sampler_name());
             let texture = gl.create_texture()
                 .ok_or(CmcError::missing_val("Texture creation"))?;
-            gl.bind_texture(image.target, Some(&texture));
-            gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_WRAP_S, WebGL::MIRRORED_REPEAT as i32);
-            gl.tex_parameteri(WebGL::TEXTURE_2D, WebGL::TEXTURE_WRAP_T, WebGL::MIRRORED_REPEAT as i32);
-
-            gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-                image.target, image.level, image.internal_format, image.width, image.height, image.border, image.format, image.data_type, Some(image.data.as_slice()))?;
-            gl.generate_mipmap(image.target);
-            textures.push((texture, u_texture, image.target));
+            textures.push(RenderTexture {
+                slot: gob_texture.slot,
+                image_index: gob_texture.image_index,
+                texture,
+                u_texture,
+                target: WebGL::TEXTURE_2D,
+                sampler: gob_texture.sampler,
+                available: Cell::new(false),
+            });
+            let texture = textures.last().unwrap();
+            match &gob_texture.status {
+                TextureStatus::Available(image) => {
+                    upload_image(gl, texture, image, name)?;
+                    texture.available.set(true);
+                },
+                TextureStatus::Pending => upload_image(gl, texture, &GobImage::placeholder(), name)?,
+            }
         }
         let mut lights: Vec<RenderLight> = Vec::new();
         for i in 0..MAX_LIGHTS {
-            lights.push(RenderLight::new_at_index(gl, &program, "spot_lights", i)?);
+            lights.push(RenderLight::new_at_index(gl, &program, "lights", i)?);
         }
 
+        let lines = match gob.edge_indices() {
+            Some((edge_buffer, count, data_type)) => {
+                let buffer = gl.create_buffer()
+                    .ok_or(CmcError::missing_val("Failed to create edge buffer"))?;
+                gl.bind_buffer(WebGL::ELEMENT_ARRAY_BUFFER, Some(&buffer));
+                gl.buffer_data_with_u8_array(WebGL::ELEMENT_ARRAY_BUFFER, edge_buffer.data.as_slice(), WebGL::STATIC_DRAW);
+                check_gl_error(gl, || format!("{}: edge buffer upload", name));
+                Some(RenderLines { buffer, count: count as i32, data_type })
+            },
+            None => None,
+        };
+        let u_wireframe = gl.get_uniform_location(&program, "uWireframe")
+            .ok_or(CmcError::missing_val("uWireframe"))?;
+        let u_wireframe_color = gl.get_uniform_location(&program, "uWireframeColor")
+            .ok_or(CmcError::missing_val("uWireframeColor"))?;
+
+        let u_texture_transform = gl.get_uniform_location(&program, "uTextureTransform")
+            .ok_or(CmcError::missing_val("uTextureTransform"))?;
+
+        let u_contact_shadow = gl.get_uniform_location(&program, "uContactShadow")
+            .ok_or(CmcError::missing_val("uContactShadow"))?;
+
+        let a_color0 = attr_location(gl, &program, &GobDataAttribute::Colors(0));
+        let u_environment = gl.get_uniform_location(&program, "uEnvironment")
+            .ok_or(CmcError::missing_val("uEnvironment"))?;
+        let u_environment_intensity = gl.get_uniform_location(&program, "uEnvironmentIntensity")
+            .ok_or(CmcError::missing_val("uEnvironmentIntensity"))?;
+        let u_alpha_mode = gl.get_uniform_location(&program, "uAlphaMode")
+            .ok_or(CmcError::missing_val("uAlphaMode"))?;
+        let u_alpha_cutoff = gl.get_uniform_location(&program, "uAlphaCutoff")
+            .ok_or(CmcError::missing_val("uAlphaCutoff"))?;
+        let u_num_lights = gl.get_uniform_location(&program, "uNumLights")
+            .ok_or(CmcError::missing_val("uNumLights"))?;
+        let u_has_base_color_texture = gl.get_uniform_location(&program, "uHasBaseColorTexture")
+            .ok_or(CmcError::missing_val("uHasBaseColorTexture"))?;
+        let u_base_color_factor = gl.get_uniform_location(&program, "uBaseColorFactor")
+            .ok_or(CmcError::missing_val("uBaseColorFactor"))?;
+
         let scene = RenderScene::new(gl, &program)?;
         Ok(ShapeRenderer {
             name: name.clone(),
+            model: model.to_string(),
+            extras: None,
+            base_transform,
+            front_face: Cell::new(WebGL::CCW),
+            lights_dropped: Cell::new(0),
+            parts: Vec::new(),
             gob,
             program,
             geometry_buffers,
             lights,
             textures,
+            lines,
+            thick_lines: RefCell::new(None),
+            u_wireframe,
+            u_wireframe_color,
+            u_texture_transform,
+            u_contact_shadow,
+            a_color0,
+            u_environment,
+            u_environment_intensity,
+            u_alpha_mode,
+            u_alpha_cutoff,
+            u_num_lights,
+            u_has_base_color_texture,
+            u_base_color_factor,
             scene,
         })
     }
 
+    /// Fills in a texture that was still Pending when this renderer was built.
+    pub fn upload_texture(&self, gl: &WebGlRenderingContext, image_index: usize, image: &GobImage) -> CmcResult<()> {
+        for texture in self.textures.iter().filter(|t| t.image_index == image_index) {
+            if !texture.available.get() {
+                log::debug!("{}: texture {} went from pending to available", self.name, image_index);
+            }
+            upload_image(gl, texture, image, &self.name)?;
+            texture.available.set(true);
+        }
+        for part in self.parts.iter() {
+            part.upload_texture(gl, image_index, image)?;
+        }
+        Ok(())
+    }
+
+    pub fn add_part(&mut self, part: ShapeRenderer) {
+        self.parts.push(part);
+    }
+
+    pub fn set_clockwise(&self, clockwise: bool) {
+        self.front_face.set(if clockwise { WebGL::CW } else { WebGL::CCW });
+        for part in self.parts.iter() {
+            part.set_clockwise(clockwise);
+        }
+    }
+
+    pub fn is_clockwise(&self) -> bool {
+        self.front_face.get() == WebGL::CW
+    }
+
+    /// Drawn after everything opaque, with blending.
+    pub fn is_transparent(&self) -> bool {
+        self.gob.alpha_mode == GobAlphaMode::Blend || self.gob.has_vertex_alpha()
+            || self.parts.iter().any(|part| part.is_transparent())
+    }
+
+    /// Local bounds of the geometry, with the node transform already applied.
+    pub fn bounds(&self) -> Option<Aabb> {
+        let own = self.gob.bounds.map(|b| b.transformed(&self.base_transform));
+        self.parts.iter()
+            .filter_map(|part| part.bounds())
+            .fold(own, |all, part| Some(all.map_or(part, |all| all.union(&part))))
+    }
+
     pub fn render(
         &self,
         gl: &WebGlRenderingContext,
         scene: &Scene,
         lights: &Vec<Light>,
-        location: &Vector3<f32>,
-        rotation: &Vector3<f32>,
+        model_mat: &Matrix4<f32>,
+        settings: &RenderSettings,
     ) {
+        for part in self.parts.iter() {
+            part.render(gl, scene, lights, model_mat, settings);
+        }
         gl.use_program(Some(&self.program));
         for (_key, gob_acc) in self.gob.accessors.iter().filter(|v| *v.0 != GobDataAttribute::Indices) {
             if let Some(gl_attr_index) = gob_acc.gl_attribute_index {
@@ -272,23 +547,155 @@ impl ShapeRenderer {
                 gl.enable_vertex_attrib_array(gl_attr_index);
             }
         }
-        for (index, (texture, utexture, target)) in self.textures.iter().enumerate() {
-            gl.active_texture(WebGL::TEXTURE0 + index as u32);
-            gl.bind_texture(*target, Some(texture));
-            gl.uniform1i(Some(utexture), index as i32);
+        if let Some(a_color0) = self.a_color0 {
+            if !self.gob.accessors.contains_key(&GobDataAttribute::Colors(0)) {
+                // Another renderer may have left the array enabled on this location
+                gl.disable_vertex_attrib_array(a_color0);
+                gl.vertex_attrib4f(a_color0, 1., 1., 1., 1.);
+            }
+        }
+        for texture in self.textures.iter() {
+            gl.active_texture(WebGL::TEXTURE0 + texture.slot.unit());
+            gl.bind_texture(texture.target, Some(&texture.texture));
+            if let Some(u_texture) = &texture.u_texture {
+                gl.uniform1i(Some(u_texture), texture.slot.unit() as i32);
+            }
         }
+        gl.active_texture(WebGL::TEXTURE0);
+        let has_base_color = self.textures.iter().any(|t| t.slot == TextureSlot::BaseColor);
+        gl.uniform1i(Some(&self.u_has_base_color_texture), has_base_color as i32);
+        gl.uniform4fv_with_f32_array(Some(&self.u_base_color_factor), &self.gob.base_color_factor);
+        gl.uniform_matrix3fv_with_f32_array(Some(&self.u_texture_transform), false, self.gob.uv_transform.as_slice());
 
-        let model_mat = Isometry3::new(location.clone(), rotation.clone()).to_homogeneous();
+        let model_mat = model_mat * self.base_transform;
         self.scene.populate_with(gl, scene, &model_mat);
 
-        for (index, light) in lights.iter().enumerate() {
-            self.lights[index].populate_with(gl, light);
+        // Only MAX_LIGHTS fit in the shader, keep the ones that matter most to this object
+        let center = Vector3::new(model_mat[(0, 3)], model_mat[(1, 3)], model_mat[(2, 3)]);
+        let mut chosen: Vec<&Light> = lights.iter().collect();
+        let dropped = chosen.len().saturating_sub(MAX_LIGHTS);
+        if dropped > 0 {
+            chosen.sort_by(|a, b| b.influence_at(&center).partial_cmp(&a.influence_at(&center)).unwrap_or(std::cmp::Ordering::Equal));
+            chosen.truncate(MAX_LIGHTS);
+        }
+        if self.lights_dropped.replace(dropped) != dropped && dropped > 0 {
+            log::warn!("{}: {} lights, only the {} strongest are used, {} dropped", self.name, lights.len(), MAX_LIGHTS, dropped);
+        }
+        for (index, render_light) in self.lights.iter().enumerate() {
+            match chosen.get(index) {
+                Some(light) => render_light.populate_with(gl, light),
+                None => render_light.clear(gl),
+            }
+        }
+        gl.uniform1i(Some(&self.u_num_lights), chosen.len() as i32);
+
+        let contact_strength = if settings.contact_shadow { settings.contact_shadow_strength } else { 0. };
+        // Each primitive blends by its own material, parts are drawn by their own render call
+        let (alpha_mode, alpha_cutoff) = match self.gob.alpha_mode {
+            GobAlphaMode::Blend => (2, 0.),
+            _ if self.gob.has_vertex_alpha() => (2, 0.),
+            GobAlphaMode::Mask(cutoff) => (1, cutoff),
+            _ => (0, 0.),
+        };
+        gl.uniform1i(Some(&self.u_alpha_mode), alpha_mode);
+        gl.uniform1f(Some(&self.u_alpha_cutoff), alpha_cutoff);
+        gl.uniform1i(Some(&self.u_environment), ENVIRONMENT_UNIT as i32);
+        let environment_intensity = if settings.environment { settings.environment_intensity } else { 0. };
+        gl.uniform1f(Some(&self.u_environment_intensity), environment_intensity);
+        gl.uniform2f(Some(&self.u_contact_shadow), contact_strength, settings.contact_shadow_height.max(f32::EPSILON));
+
+        let overlay = if settings.wireframe_overlay { self.lines.as_ref() } else { None };
+        gl.uniform1i(Some(&self.u_wireframe), 0);
+        if overlay.is_some() {
+            // Push the surface back a touch so its own edges win the depth test
+            gl.enable(WebGL::POLYGON_OFFSET_FILL);
+            gl.polygon_offset(1., 1.);
         }
 
         let gob_acc = self.gob.accessors.get(&GobDataAttribute::Indices).unwrap();
         gl.bind_buffer(WebGL::ELEMENT_ARRAY_BUFFER, Some(&self.geometry_buffers[&gob_acc.buffer_index]));
 
+        let clockwise = self.is_clockwise();
+        if clockwise {
+            gl.front_face(WebGL::CW);
+        }
+        // Only on for mesh triangles, the line and full screen passes don't care which way they wind
+        let cull = settings.cull_faces && !self.gob.double_sided;
+        if cull {
+            gl.enable(WebGL::CULL_FACE);
+        }
         gl.draw_elements_with_i32(WebGL::TRIANGLES, gob_acc.count as i32, gob_acc.data_type, gob_acc.offset);
+        if cull {
+            gl.disable(WebGL::CULL_FACE);
+        }
+        if clockwise {
+            gl.front_face(WebGL::CCW);
+        }
+        check_gl_error(gl, || format!("{}: draw", self.name));
+
+        if overlay.is_some() && settings.wireframe_width > 1. {
+            gl.disable(WebGL::POLYGON_OFFSET_FILL);
+            self.render_thick_lines(gl, scene, &model_mat, settings);
+        } else if let Some(lines) = overlay {
+            gl.disable(WebGL::POLYGON_OFFSET_FILL);
+            gl.uniform1i(Some(&self.u_wireframe), 1);
+            gl.uniform3fv_with_f32_array(Some(&self.u_wireframe_color), &settings.wireframe_color);
+            gl.bind_buffer(WebGL::ELEMENT_ARRAY_BUFFER, Some(&lines.buffer));
+            gl.draw_elements_with_i32(WebGL::LINES, lines.count, lines.data_type, 0);
+            check_gl_error(gl, || format!("{}: wireframe draw", self.name));
+        }
+    }
+
+    fn render_thick_lines(&self, gl: &WebGlRenderingContext, scene: &Scene, model_mat: &Matrix4<f32>, settings: &RenderSettings) {
+        let mut thick_lines = self.thick_lines.borrow_mut();
+        if thick_lines.is_none() {
+            let built = self.gob.thick_line_vertices()
+                .ok_or(CmcError::missing_val("Edges for thick lines"))
+                .and_then(|vertices| ThickLines::new(gl, &vertices));
+            match built {
+                Ok(built) => *thick_lines = Some(built),
+                Err(e) => return log::warn!("{}: no thick wireframe: {}", self.name, e),
+            }
+        }
+        if let Some(thick_lines) = thick_lines.as_ref() {
+            let view = Matrix4::from_column_slice(&scene.get_view_as_vec());
+            let projection = Matrix4::from_column_slice(&scene.get_projection_as_vec());
+            let mvp = projection * view * model_mat;
+            thick_lines.render(gl, &mvp, scene.viewport(), settings.wireframe_width, &settings.wireframe_color);
+        }
     }
 }
 
+/// Applies the texture's sampler. WebGL1 can't repeat or mipmap textures whose sides aren't
+/// powers of two, there those are clamped and filtered without mips instead of sampling black.
+fn upload_image(gl: &WebGlRenderingContext, texture: &RenderTexture, image: &GobImage, name: &str) -> CmcResult<()> {
+    gl.bind_texture(texture.target, Some(&texture.texture));
+    gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        image.target, image.level, image.internal_format, image.width, image.height, image.border, image.format, image.data_type, Some(image.data.as_slice()))?;
+
+    let sampler = texture.sampler;
+    let mag_filter = sampler.mag_filter.unwrap_or(WebGL::LINEAR);
+    let min_filter = sampler.min_filter.unwrap_or(WebGL::LINEAR_MIPMAP_LINEAR);
+    let power_of_two = |v: i32| v > 0 && (v & (v - 1)) == 0;
+    let (wrap_s, wrap_t, min_filter) = if is_webgl2(gl) || (power_of_two(image.width) && power_of_two(image.height)) {
+        (sampler.wrap_s, sampler.wrap_t, min_filter)
+    } else {
+        if sampler.wrap_s != WebGL::CLAMP_TO_EDGE || sampler.wrap_t != WebGL::CLAMP_TO_EDGE {
+            log::warn!("{}: texture {} is {}x{}, it can't repeat in WebGL1 so it is clamped", name, texture.image_index, image.width, image.height);
+        }
+        let min_filter = match min_filter {
+            WebGL::NEAREST | WebGL::NEAREST_MIPMAP_NEAREST | WebGL::NEAREST_MIPMAP_LINEAR => WebGL::NEAREST,
+            _ => WebGL::LINEAR,
+        };
+        (WebGL::CLAMP_TO_EDGE, WebGL::CLAMP_TO_EDGE, min_filter)
+    };
+    gl.tex_parameteri(texture.target, WebGL::TEXTURE_WRAP_S, wrap_s as i32);
+    gl.tex_parameteri(texture.target, WebGL::TEXTURE_WRAP_T, wrap_t as i32);
+    gl.tex_parameteri(texture.target, WebGL::TEXTURE_MAG_FILTER, mag_filter as i32);
+    gl.tex_parameteri(texture.target, WebGL::TEXTURE_MIN_FILTER, min_filter as i32);
+    if min_filter != WebGL::NEAREST && min_filter != WebGL::LINEAR {
+        gl.generate_mipmap(image.target);
+    }
+    check_gl_error(gl, || format!("{}: texture {} upload", name, texture.image_index));
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use crate::error::{CmcError, CmcResult};
+use super::common::{build_program, check_gl_error};
+use nalgebra::Matrix4;
+use web_sys::WebGlRenderingContext as WebGL;
+use web_sys::*;
+
+// WebGL caps lineWidth at 1 nearly everywhere, so each edge is widened into a quad in screen space
+const VERT_SHADER: &str = r#"
+    attribute vec3 aPosition;
+    attribute vec3 aOther;
+    attribute float aSide;
+
+    uniform mat4 uModelViewProjection;
+    uniform vec2 uViewport;
+    uniform float uWidth;
+
+    void main() {
+        vec4 position = uModelViewProjection * vec4(aPosition, 1.0);
+        vec4 other = uModelViewProjection * vec4(aOther, 1.0);
+        vec2 screen_dir = normalize(other.xy / other.w * uViewport - position.xy / position.w * uViewport);
+        vec2 normal = vec2(-screen_dir.y, screen_dir.x);
+        gl_Position = position + vec4(normal * uWidth * aSide / uViewport * position.w, 0.0, 0.0);
+    }
+"#;
+const FRAG_SHADER: &str = r#"
+    precision mediump float;
+    uniform vec3 uColor;
+
+    void main() {
+        gl_FragColor = vec4(uColor, 1.0);
+    }
+"#;
+const FLOATS_PER_VERTEX: i32 = 7;
+
+/// Edges drawn as quads a fixed number of pixels wide, see Gob::thick_line_vertices.
+pub struct ThickLines {
+    program: WebGlProgram,
+    buffer: WebGlBuffer,
+    count: i32,
+    a_position: u32,
+    a_other: u32,
+    a_side: u32,
+    u_mvp: WebGlUniformLocation,
+    u_viewport: WebGlUniformLocation,
+    u_width: WebGlUniformLocation,
+    u_color: WebGlUniformLocation,
+}
+
+impl ThickLines {
+    pub fn new(gl: &WebGlRenderingContext, vertices: &[f32]) -> CmcResult<Self> {
+        let program = build_program(gl, VERT_SHADER, FRAG_SHADER)?;
+        let buffer = gl.create_buffer()
+            .ok_or(CmcError::missing_val("Failed to create thick line buffer"))?;
+        let bytes: Vec<u8> = vertices.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        gl.bind_buffer(WebGL::ARRAY_BUFFER, Some(&buffer));
+        gl.buffer_data_with_u8_array(WebGL::ARRAY_BUFFER, bytes.as_slice(), WebGL::STATIC_DRAW);
+        check_gl_error(gl, || "thick line buffer upload");
+        let attrib = |name: &str| -> CmcResult<u32> {
+            let location = gl.get_attrib_location(&program, name);
+            if location < 0 {
+                Err(CmcError::missing_val(name))
+            } else {
+                Ok(location as u32)
+            }
+        };
+        let uniform = |name: &str| gl.get_uniform_location(&program, name).ok_or(CmcError::missing_val(name));
+        Ok(Self {
+            a_position: attrib("aPosition")?,
+            a_other: attrib("aOther")?,
+            a_side: attrib("aSide")?,
+            u_mvp: uniform("uModelViewProjection")?,
+            u_viewport: uniform("uViewport")?,
+            u_width: uniform("uWidth")?,
+            u_color: uniform("uColor")?,
+            count: vertices.len() as i32 / FLOATS_PER_VERTEX,
+            program,
+            buffer,
+        })
+    }
+
+    /// width is in pixels. Leaves the caller's program unbound.
+    pub fn render(&self, gl: &WebGlRenderingContext, mvp: &Matrix4<f32>, viewport: [f32; 2], width: f32, color: &[f32; 3]) {
+        gl.use_program(Some(&self.program));
+        gl.bind_buffer(WebGL::ARRAY_BUFFER, Some(&self.buffer));
+        let float = std::mem::size_of::<f32>() as i32;
+        let stride = FLOATS_PER_VERTEX * float;
+        gl.vertex_attrib_pointer_with_i32(self.a_position, 3, WebGL::FLOAT, false, stride, 0);
+        gl.enable_vertex_attrib_array(self.a_position);
+        gl.vertex_attrib_pointer_with_i32(self.a_other, 3, WebGL::FLOAT, false, stride, 3 * float);
+        gl.enable_vertex_attrib_array(self.a_other);
+        gl.vertex_attrib_pointer_with_i32(self.a_side, 1, WebGL::FLOAT, false, stride, 6 * float);
+        gl.enable_vertex_attrib_array(self.a_side);
+
+        gl.uniform_matrix4fv_with_f32_array(Some(&self.u_mvp), false, mvp.as_slice());
+        gl.uniform2f(Some(&self.u_viewport), viewport[0], viewport[1]);
+        gl.uniform1f(Some(&self.u_width), width);
+        gl.uniform3fv_with_f32_array(Some(&self.u_color), color);
+        gl.draw_arrays(WebGL::TRIANGLES, 0, self.count);
+        gl.disable_vertex_attrib_array(self.a_position);
+        gl.disable_vertex_attrib_array(self.a_other);
+        gl.disable_vertex_attrib_array(self.a_side);
+        check_gl_error(gl, || "thick line draw");
+    }
+}
@@ -1,11 +1,91 @@
-use crate::key_state::KeyState;
-use nalgebra::{Isometry3, Matrix3x1, Perspective3, Point3, Unit, UnitQuaternion, Vector3};
+use crate::{bounds::Aabb, error::{CmcError, CmcResult}, input_settings::InputSettings, key_state::KeyState};
+use nalgebra::{Isometry3, Matrix3x1, Matrix4, Orthographic3, Perspective3, Point3, Point4, Unit, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
 
 pub const FIELD_OF_VIEW: f32 = 45. * std::f32::consts::PI / 180.; //in radians
 pub const Z_FAR: f32 = 1000.;
 pub const Z_NEAR: f32 = 1.0;
+pub const AMBIENT_LIGHT: [f32; 3] = [0.1, 0.1, 0.1];
 
-const MAX_SPEED: f32 = 0.25;
+const MIN_FOV: f32 = 10. * std::f32::consts::PI / 180.;
+const MAX_FOV: f32 = 120. * std::f32::consts::PI / 180.;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum ToneMap {
+    None = 0,
+    Reinhard = 1,
+    Aces = 2,
+}
+
+impl ToneMap {
+    pub fn from_index(index: u32) -> Option<Self> {
+        match index {
+            0 => Some(ToneMap::None),
+            1 => Some(ToneMap::Reinhard),
+            2 => Some(ToneMap::Aces),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum ProjectionMode {
+    /// fov is vertical and in radians.
+    Perspective { fov: f32 },
+    /// height is how many world units fit top to bottom, width follows the aspect.
+    Orthographic { height: f32 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PresetView {
+    Front,
+    Top,
+    Side,
+    Iso,
+}
+
+impl PresetView {
+    pub fn from_index(index: u32) -> Option<Self> {
+        match index {
+            1 => Some(PresetView::Front),
+            2 => Some(PresetView::Top),
+            3 => Some(PresetView::Side),
+            4 => Some(PresetView::Iso),
+            _ => None,
+        }
+    }
+
+    /// Unit direction from the target back toward the eye.
+    fn eye_direction(&self) -> Vector3<f32> {
+        match self {
+            // Straight down makes look_at degenerate, so lean in a hair from the front
+            PresetView::Top => Vector3::new(0., 1., 0.01).normalize(),
+            PresetView::Front => Vector3::z(),
+            PresetView::Side => Vector3::x(),
+            PresetView::Iso => Vector3::repeat(1.).normalize(),
+        }
+    }
+}
+
+/// What gets saved of a Scene, the look vectors are rebuilt from the target on load.
+#[derive(Deserialize, Serialize)]
+struct SavedScene {
+    eye: [f32; 3],
+    target: [f32; 3],
+    width: f32,
+    height: f32,
+    projection: ProjectionMode,
+    z_near: f32,
+    z_far: f32,
+    exposure: f32,
+    tone_map: ToneMap,
+    #[serde(default = "default_ambient")]
+    ambient: [f32; 3],
+}
+
+fn default_ambient() -> [f32; 3] {
+    AMBIENT_LIGHT
+}
 
 #[derive(Clone)]
 pub struct Scene {
@@ -15,6 +95,13 @@ pub struct Scene {
     look_dir_up: Vector3<f32>,
     width: f32,
     height: f32,
+    projection: ProjectionMode,
+    z_near: f32,
+    z_far: f32,
+    exposure: f32,
+    tone_map: ToneMap,
+    /// Linear light every surface gets regardless of the lights.
+    ambient: [f32; 3],
 }
 
 impl Scene {
@@ -26,14 +113,64 @@ impl Scene {
         let eye = Point3::from(eye);
         Self {
             eye, look_dir, look_dir_left, look_dir_up, width, height,
+            projection: ProjectionMode::Perspective { fov: FIELD_OF_VIEW },
+            z_near: Z_NEAR,
+            z_far: Z_FAR,
+            exposure: 1.,
+            tone_map: ToneMap::None,
+            ambient: AMBIENT_LIGHT,
         }
     }
 
-    pub fn get_view_as_vec(&self) -> Vec<f32> {
+    pub fn to_json(&self) -> CmcResult<String> {
+        let target = self.eye + self.look_dir;
+        let saved = SavedScene {
+            eye: self.eye.coords.into(),
+            target: target.coords.into(),
+            width: self.width,
+            height: self.height,
+            projection: self.projection,
+            z_near: self.z_near,
+            z_far: self.z_far,
+            exposure: self.exposure,
+            tone_map: self.tone_map,
+            ambient: self.ambient,
+        };
+        Ok(serde_json::to_string(&saved)?)
+    }
+
+    pub fn from_json(json: &str) -> CmcResult<Self> {
+        let saved: SavedScene = serde_json::from_str(json)?;
+        let mut scene = Scene::new(saved.eye, saved.width, saved.height);
+        scene.look_at(saved.target);
+        scene.set_projection_mode(saved.projection)?;
+        scene.set_clip_planes(saved.z_near, saved.z_far)?;
+        scene.set_exposure(saved.exposure)?;
+        scene.tone_map = saved.tone_map;
+        scene.ambient = saved.ambient;
+        Ok(scene)
+    }
+
+    fn view_matrix(&self) -> Matrix4<f32> {
         // log::info!("Looking at: ({:?})", self.look_dir);
         let target = Point3::from(self.eye + self.look_dir);
         let view = Isometry3::look_at_rh(&self.eye, &target, &Vector3::y());
-        view.to_homogeneous().as_slice().to_vec()
+        view.to_homogeneous()
+    }
+
+    fn projection_matrix(&self) -> Matrix4<f32> {
+        let aspect: f32 = self.width / self.height;
+        match self.projection {
+            ProjectionMode::Perspective { fov } => Perspective3::new(aspect, fov, self.z_near, self.z_far).to_homogeneous(),
+            ProjectionMode::Orthographic { height } => {
+                let (half_width, half_height) = (height * aspect / 2., height / 2.);
+                Orthographic3::new(-half_width, half_width, -half_height, half_height, self.z_near, self.z_far).to_homogeneous()
+            },
+        }
+    }
+
+    pub fn get_view_as_vec(&self) -> Vec<f32> {
+        self.view_matrix().as_slice().to_vec()
     }
 
     pub fn get_eye_as_vec(&self) -> Vec<f32> {
@@ -41,9 +178,53 @@ impl Scene {
     }
 
     pub fn get_projection_as_vec(&self) -> Vec<f32> {
-        let aspect: f32 = self.width / self.height;
-        let projection = Perspective3::new(aspect, FIELD_OF_VIEW, Z_NEAR, Z_FAR);
-        projection.to_homogeneous().as_slice().to_vec()
+        self.projection_matrix().as_slice().to_vec()
+    }
+
+    /// Turns a pixel coordinate (origin top left) into a world space ray leaving the eye.
+    pub fn screen_to_world_ray(&self, screen_x: f32, screen_y: f32) -> Option<(Point3<f32>, Vector3<f32>)> {
+        if !self.has_area() {
+            return None;
+        }
+        let ndc_x = 2. * screen_x / self.width - 1.;
+        let ndc_y = 1. - 2. * screen_y / self.height;
+        let inverse = (self.projection_matrix() * self.view_matrix()).try_inverse()?;
+        let near = inverse * Point4::new(ndc_x, ndc_y, -1., 1.);
+        let far = inverse * Point4::new(ndc_x, ndc_y, 1., 1.);
+        let near = Point3::from_homogeneous(near.coords)?;
+        let far = Point3::from_homogeneous(far.coords)?;
+        Some((near, (far - near).normalize()))
+    }
+
+    /// Pixel coordinate (origin top left) of a world point, None when it is behind the eye.
+    pub fn world_to_screen(&self, point: &Point3<f32>) -> Option<[f32; 2]> {
+        if !self.has_area() {
+            return None;
+        }
+        let clip = self.projection_matrix() * self.view_matrix() * point.to_homogeneous();
+        if clip.w <= 0. {
+            return None;
+        }
+        let ndc_x = clip.x / clip.w;
+        let ndc_y = clip.y / clip.w;
+        Some([(ndc_x + 1.) * self.width / 2., (1. - ndc_y) * self.height / 2.])
+    }
+
+    pub fn get_eye(&self) -> Point3<f32> {
+        self.eye
+    }
+
+    /// Where the ray under a pixel hits the y = 0 ground plane, if it does.
+    pub fn screen_to_ground(&self, screen_x: f32, screen_y: f32) -> Option<Point3<f32>> {
+        let (origin, dir) = self.screen_to_world_ray(screen_x, screen_y)?;
+        if dir.y.abs() < f32::EPSILON {
+            return None;
+        }
+        let distance = -origin.y / dir.y;
+        if distance < 0. {
+            return None;
+        }
+        Some(origin + dir * distance)
     }
 
     pub fn move_relative(&mut self, offset: [f32; 3]) {
@@ -51,12 +232,124 @@ impl Scene {
         self.eye = new_position;
     }
 
+    /// Walks the eye along the way it is looking, negative backs up. The view direction is kept.
+    pub fn dolly(&mut self, amount: f32) {
+        self.eye += self.look_dir * amount;
+    }
+
     pub fn move_absolute(&mut self, position: [f32; 3]) {
         self.eye = Point3::from(position)
     }
 
-    pub fn mouse_rotate(&mut self, rotations: [f32; 3]) {
-        let sensi = 1. / 100.;
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Multiplies the light before tone mapping, 0 or more.
+    pub fn set_exposure(&mut self, exposure: f32) -> CmcResult<()> {
+        if !(exposure.is_finite() && exposure >= 0.) {
+            return Err(CmcError::conversion_failed(format!("Exposure {}", exposure)));
+        }
+        self.exposure = exposure;
+        Ok(())
+    }
+
+    pub fn tone_map(&self) -> ToneMap {
+        self.tone_map
+    }
+
+    pub fn ambient(&self) -> [f32; 3] {
+        self.ambient
+    }
+
+    pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+        self.ambient = ambient;
+    }
+
+    pub fn set_tone_map(&mut self, tone_map: ToneMap) {
+        self.tone_map = tone_map;
+    }
+
+    /// fov is vertical and in radians, this switches back to perspective.
+    pub fn set_lens(&mut self, fov: f32, z_near: f32, z_far: f32) {
+        self.projection = ProjectionMode::Perspective { fov };
+        self.z_near = z_near;
+        self.z_far = z_far;
+    }
+
+    /// Near has to be in front of the eye and far beyond it, or the projection can't be built.
+    pub fn set_clip_planes(&mut self, z_near: f32, z_far: f32) -> CmcResult<()> {
+        if !(z_near > 0. && z_far > z_near) {
+            return Err(CmcError::conversion_failed(format!("Clip planes {} to {}", z_near, z_far)));
+        }
+        self.z_near = z_near;
+        self.z_far = z_far;
+        Ok(())
+    }
+
+    /// A perspective fov is clamped to the range zoom allows, an orthographic height kept above 0.
+    pub fn set_projection_mode(&mut self, projection: ProjectionMode) -> CmcResult<()> {
+        self.projection = match projection {
+            ProjectionMode::Perspective { fov } if fov.is_finite() => ProjectionMode::Perspective { fov: fov.clamp(MIN_FOV, MAX_FOV) },
+            ProjectionMode::Orthographic { height } if height.is_finite() => ProjectionMode::Orthographic { height: height.max(f32::EPSILON) },
+            _ => return Err(CmcError::conversion_failed(format!("Projection {:?}", projection))),
+        };
+        Ok(())
+    }
+
+    pub fn look_at(&mut self, target: [f32; 3]) {
+        self.turn_towards(self.look_dir, target, 1.);
+    }
+
+    pub fn look_dir(&self) -> Vector3<f32> {
+        self.look_dir
+    }
+
+    /// Looks amount (0 to 1) of the way from the from direction towards target, for easing onto it.
+    pub fn turn_towards(&mut self, from: Vector3<f32>, target: [f32; 3], amount: f32) {
+        let look_dir = Point3::from(target) - self.eye;
+        if look_dir.norm() < f32::EPSILON {
+            return;
+        }
+        let look_dir = look_dir.normalize();
+        let eased = from.lerp(&look_dir, amount.clamp(0., 1.));
+        // Right behind, the blend passes through zero, so just snap
+        self.look_dir = if eased.norm() < 0.01 { look_dir } else { eased.normalize() };
+        self.look_dir_left = self.look_dir.cross(&Vector3::y());
+        self.look_dir_up = self.look_dir.cross(&self.look_dir_left);
+    }
+
+    /// Moves the eye so that everything inside bounds is in frame from the given direction.
+    pub fn set_preset_view(&mut self, view: PresetView, bounds: &Aabb) {
+        let center = bounds.center();
+        let radius = bounds.radius().max(1.);
+        let distance = match &mut self.projection {
+            ProjectionMode::Perspective { fov } => radius / (*fov / 2.).sin(),
+            ProjectionMode::Orthographic { height } => {
+                // Distance doesn't size anything here, just keep the eye clear of the bounds
+                *height = radius * 2.;
+                radius + self.z_near * 2.
+            },
+        };
+        self.eye = center + view.eye_direction() * distance;
+        self.look_at([center.x, center.y, center.z]);
+    }
+
+    /// Narrows (positive) or widens (negative) the field of view, or the orthographic height alike.
+    pub fn zoom(&mut self, amount: f32) {
+        match &mut self.projection {
+            ProjectionMode::Perspective { fov } => *fov = nalgebra::clamp(*fov - amount, MIN_FOV, MAX_FOV),
+            ProjectionMode::Orthographic { height } => *height = (*height * (1. - amount)).max(f32::EPSILON),
+        }
+    }
+
+    pub fn mouse_rotate(&mut self, rotations: [f32; 3], settings: &InputSettings) {
+        let sensi = settings.rotate;
+        let rotations = if settings.invert_y {
+            [rotations[0], -rotations[1], rotations[2]]
+        } else {
+            rotations
+        };
         let min_angle = f32::from(10.).to_radians();
         let max_angle = f32::from(170.).to_radians();
         let x_rot_angle = sensi * rotations[1];
@@ -84,7 +377,22 @@ impl Scene {
         self.height = height;
     }
 
-    pub fn update_from_key_state(&mut self, key_state: &KeyState) {
+    /// (near, far)
+    pub fn clip_planes(&self) -> (f32, f32) {
+        (self.z_near, self.z_far)
+    }
+
+    pub fn viewport(&self) -> [f32; 2] {
+        [self.width, self.height]
+    }
+
+    /// False while the canvas hasn't been laid out, the projection would divide by zero.
+    pub fn has_area(&self) -> bool {
+        self.width > 0. && self.height > 0.
+    }
+
+    /// elapsed is the frame time in ms.
+    pub fn update_from_key_state(&mut self, key_state: &KeyState, settings: &InputSettings, elapsed: f32) {
         let fwbw = match (key_state.forward, key_state.backward) {
             (true, true) | (false, false) => 0.,
             (true, false) => 1.,
@@ -101,7 +409,7 @@ impl Scene {
         let fwbw : Vector3<f32> = fwbw * self.look_dir;
         let lr = lr * self.look_dir_left;
         let movement_vec = Vector3::from(fwbw + lr).normalize();
-        let movement_vec = MAX_SPEED * movement_vec;
+        let movement_vec = settings.pan * elapsed / 1000. * movement_vec;
         self.move_relative([movement_vec.x, movement_vec.y, movement_vec.z]);
     }
 }
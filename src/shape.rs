@@ -1,7 +1,56 @@
-use crate::{light::Light, render::ShapeRenderer, entity::Entity, scene::Scene};
+use crate::{bounds::Aabb, error::{CmcError, CmcResult}, light::Light, render::{RenderSettings, ShapeRenderer}, entity::Entity, scene::Scene, uid::Uid};
+use nalgebra::{Matrix4, Vector3};
+use serde::{Deserialize, Serialize};
 use web_sys::WebGlRenderingContext;
 use std::rc::Rc;
 
+/// A built in primitive at a given size, as JSON like {"type": "Ball", "radius": 0.5}.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum ShapeDescriptor {
+    Cuboid { half_extents: [f32; 3] },
+    Ball { radius: f32 },
+    /// Along Y, half_height is the straight part only, the caps add radius at each end.
+    Capsule { half_height: f32, radius: f32 },
+}
+
+/// Capsules share a renderer per straight half length to radius ratio, rounded to this step.
+const CAPSULE_RATIO_STEP: f32 = 0.25;
+/// Longer capsules are drawn at this ratio, which keeps the number of capsule renderers bounded.
+const MAX_CAPSULE_RATIO: f32 = 16.;
+
+impl ShapeDescriptor {
+    /// Sizes have to be finite and above 0, except a capsule's straight part which can be 0 long.
+    pub fn validate(&self) -> CmcResult<()> {
+        let valid = match self {
+            ShapeDescriptor::Cuboid { half_extents } => half_extents.iter().all(|e| e.is_finite() && *e > 0.),
+            ShapeDescriptor::Ball { radius } => radius.is_finite() && *radius > 0.,
+            ShapeDescriptor::Capsule { half_height, radius } =>
+                half_height.is_finite() && *half_height >= 0. && radius.is_finite() && *radius > 0.,
+        };
+        if !valid {
+            return Err(CmcError::conversion_failed(format!("Shape {:?}", self)));
+        }
+        Ok(())
+    }
+
+    /// Renderer to draw it with and the scale to give it. A capsule scaled evenly keeps round
+    /// caps, so capsules are built at radius 1 and scaled by their radius.
+    pub fn renderer_and_scale(&self) -> (String, Vector3<f32>) {
+        match self {
+            ShapeDescriptor::Cuboid { half_extents } => ("prim_box".to_string(), Vector3::from(*half_extents) * 2.),
+            ShapeDescriptor::Ball { radius } => ("prim_sphere".to_string(), Vector3::repeat(radius * 2.)),
+            ShapeDescriptor::Capsule { half_height, radius } =>
+                (format!("prim_capsule_{}", capsule_ratio(*half_height, *radius)), Vector3::repeat(*radius)),
+        }
+    }
+}
+
+/// Straight half length of a radius 1 capsule with the same proportions, rounded to CAPSULE_RATIO_STEP.
+pub fn capsule_ratio(half_height: f32, radius: f32) -> f32 {
+    ((half_height / radius).min(MAX_CAPSULE_RATIO) / CAPSULE_RATIO_STEP).round() * CAPSULE_RATIO_STEP
+}
+
 pub struct Shape {
     renderer: Rc<ShapeRenderer>,
     // be cool to figure out how pre-computing the transforms on rotation and translation
@@ -9,15 +58,77 @@ pub struct Shape {
     // For now just dumping everything into entity then we'll move it into a phys from there.
     // Way to think about optimizing way too early.
     pub entity: Entity,
+    /// When set the entity is relative to the parent's world transform.
+    pub parent: Option<Uid>,
+    /// Color and pixel width of this shape's own wireframe, drawn whatever the global setting.
+    pub wireframe: Option<([f32; 3], f32)>,
+    /// Hidden shapes still move and parent things, they just aren't drawn or picked.
+    pub visible: bool,
+    /// Velocity and rotation rate from before a freeze, put back on thaw.
+    frozen: Option<(Vector3<f32>, Vector3<f32>)>,
 }
 
 impl Shape {
+    /// Starts hidden when the renderer's glTF extras say "noRender": true, for proxy geometry.
     pub fn new(renderer: Rc<ShapeRenderer>, entity: Entity) -> Self {
-        Self { renderer, entity }
+        let visible = renderer.extras.as_ref()
+            .and_then(|extras| extras.get("noRender"))
+            .and_then(|no_render| no_render.as_bool())
+            != Some(true);
+        Self { renderer, entity, parent: None, wireframe: None, visible, frozen: None }
+    }
+
+    pub fn renderer(&self) -> &Rc<ShapeRenderer> {
+        &self.renderer
+    }
+
+    pub fn set_renderer(&mut self, renderer: Rc<ShapeRenderer>) {
+        self.renderer = renderer;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    /// Holds the shape still, freezing twice keeps the motion from the first freeze.
+    pub fn freeze(&mut self) {
+        if self.frozen.is_none() {
+            self.frozen = Some((self.entity.velocity, self.entity.rotation_rate));
+            self.entity.velocity = Vector3::zeros();
+            self.entity.rotation_rate = Vector3::zeros();
+        }
+    }
+
+    /// A frozen shape keeps the rate for when it thaws.
+    pub fn set_rotation_rate(&mut self, rate: Vector3<f32>) {
+        match self.frozen.as_mut() {
+            Some((_, frozen_rate)) => *frozen_rate = rate,
+            None => crate::entity::set_rot_rate(&mut self.entity, rate),
+        }
+    }
+
+    pub fn thaw(&mut self) {
+        if let Some((velocity, rotation_rate)) = self.frozen.take() {
+            self.entity.velocity = velocity;
+            self.entity.rotation_rate = rotation_rate;
+        }
+    }
+
+    pub fn world_bounds(&self, world: &Matrix4<f32>) -> Option<Aabb> {
+        self.renderer.bounds().map(|b| b.transformed(world))
     }
 
-    pub fn render(&self, gl: &WebGlRenderingContext, scene: &Scene, lights: &Vec<Light>) {
-        self.renderer.render(gl, scene, lights, &self.entity.location, &self.entity.rotation)
+    pub fn render(&self, gl: &WebGlRenderingContext, scene: &Scene, lights: &Vec<Light>, world: &Matrix4<f32>, settings: &RenderSettings) {
+        match self.wireframe {
+            Some((color, width)) => {
+                let mut settings = settings.clone();
+                settings.wireframe_overlay = true;
+                settings.wireframe_color = color;
+                settings.wireframe_width = width;
+                self.renderer.render(gl, scene, lights, world, &settings)
+            },
+            None => self.renderer.render(gl, scene, lights, world, settings),
+        }
     }
 
 
@@ -0,0 +1,26 @@
+use nalgebra::{Matrix4, UnitQuaternion, Vector3};
+
+/// Where something is, which way it faces and how big it is, kept apart so scale survives.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self {
+            translation,
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::repeat(1.),
+        }
+    }
+
+    /// Translation * rotation * scale, scale may be non-uniform.
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
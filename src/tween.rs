@@ -0,0 +1,103 @@
+use crate::transform::Transform;
+use nalgebra::{Isometry3, Translation3, Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// 0 linear, 1 ease in, 2 ease out, 3 ease in and out.
+    pub fn from_index(index: u32) -> Option<Self> {
+        match index {
+            0 => Some(Easing::Linear),
+            1 => Some(Easing::EaseIn),
+            2 => Some(Easing::EaseOut),
+            3 => Some(Easing::EaseInOut),
+            _ => None,
+        }
+    }
+
+    /// Maps linear progress in [0, 1] onto the eased curve.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0., 1.);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2. - t),
+            Easing::EaseInOut => t * t * (3. - 2. * t),
+        }
+    }
+}
+
+pub trait Lerp: Clone {
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vector3<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Isometry3<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let translation = self.translation.vector.lerp(&other.translation.vector, t);
+        let rotation = self.rotation.slerp(&other.rotation, t);
+        Isometry3::from_parts(Translation3::from(translation), rotation)
+    }
+}
+
+impl Lerp for Transform {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Transform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+}
+
+/// Interpolates from start to end over duration, driven by the same ms deltas as entity::update.
+#[derive(Clone, Debug)]
+pub struct Tween<T: Lerp> {
+    start: T,
+    end: T,
+    duration: f32,
+    elapsed: f32,
+    easing: Easing,
+}
+
+impl<T: Lerp> Tween<T> {
+    pub fn new(start: T, end: T, duration: f32, easing: Easing) -> Self {
+        Self {
+            start,
+            end,
+            duration,
+            elapsed: 0.,
+            easing,
+        }
+    }
+
+    /// Advances by delta and returns the new value.
+    pub fn advance(&mut self, delta: f32) -> T {
+        self.elapsed = (self.elapsed + delta).min(self.duration);
+        self.value()
+    }
+
+    pub fn value(&self) -> T {
+        if self.duration <= 0. {
+            return self.end.clone();
+        }
+        self.start.lerp(&self.end, self.easing.apply(self.elapsed / self.duration))
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+use lazy_static::lazy_static;
+
+static LAST_UID: AtomicU32 = AtomicU32::new(0);
+/// Freed uids wait until this many are free before being reused, oldest first, so a uid
+/// someone still holds after a remove doesn't straight away point at a new object.
+const MIN_FREE_UIDS: usize = 1024;
+
+lazy_static! {
+    /// Uids given back by free, handed out again once enough have piled up.
+    static ref FREE_UIDS: Mutex<VecDeque<u32>> = Mutex::new(VecDeque::new());
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Uid(u32);
+
+impl Uid {
+    pub fn invalid() -> Self {
+        Uid(0)
+    }
+
+    /// Returns the uid for reuse, only once nothing refers to it any more.
+    pub fn free(uid: Uid) {
+        if uid == Uid::invalid() {
+            return;
+        }
+        let mut free = FREE_UIDS.lock().unwrap();
+        if !free.contains(&uid.0) {
+            free.push_back(uid.0);
+        }
+    }
+}
+
+pub fn get_new_uid() -> Uid {
+    {
+        let mut free = FREE_UIDS.lock().unwrap();
+        if free.len() >= MIN_FREE_UIDS {
+            if let Some(uid) = free.pop_front() {
+                return Uid(uid);
+            }
+        }
+    }
+    // Wrapping would come back round to invalid and then to live uids
+    let last = LAST_UID.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| last.checked_add(1))
+        .expect("Ran out of uids");
+    Uid(last + 1)
+}
+
+impl From<u32> for Uid {
+    fn from(item: u32) -> Self {
+        Uid(item)
+    }
+}
+
+impl From<Uid> for u32 {
+    fn from(item: Uid) -> Self {
+        item.0
+    }
+}
+
+impl fmt::Display for Uid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}